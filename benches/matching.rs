@@ -0,0 +1,143 @@
+//! Throughput of the URL matching, to catch regressions when changing how globs are translated.
+//! Run with `cargo bench`.
+
+// Only the process list is used here
+#[allow(dead_code)]
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use common::FixedProcesses;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use firefox_router::{open_links, resolve, AppConfig, FirefoxInfo, Glob, Launcher};
+use std::hint::black_box;
+use std::process::Command;
+
+/// URLs shaped like the links usually clicked: some bare, some with deep paths, queries or fragments.
+fn url_corpus() -> Vec<String> {
+    let hosts = ["example.com", "www.site3.com", "docs.work.com", "a.b.c.tracking.com", "münchen.de"];
+    let paths = ["", "/", "/page", "/a/b/c/d/e/index.html", "/search?q=rust&page=2", "/post#comments", "/caf%C3%A9"];
+    hosts.iter()
+        .flat_map(|host| paths.iter().map(move |path| format!("https://{host}{path}")))
+        .collect()
+}
+
+fn bench_glob(c: &mut Criterion) {
+    let urls = url_corpus();
+    let urls_without_protocol: Vec<&str> = urls.iter().map(|it| it.trim_start_matches("https://")).collect();
+    let patterns = [
+        ("literal", "https://example.com/page"),
+        ("subdomain", "https://*.work.com/**"),
+        ("alternation", "https://{www,docs}.*.com/{page,post}"),
+        ("query", "https://**/search?q=*"),
+        ("double_star_heavy", "**://**.**.com/**/**/**"),
+    ];
+
+    let mut group = c.benchmark_group("is_match");
+    for (name, pattern) in patterns {
+        let glob = Glob::new(pattern).unwrap();
+        group.bench_with_input(BenchmarkId::new("with_protocol", name), &glob, |b, glob| {
+            b.iter(|| urls.iter().filter(|url| glob.is_match(black_box(url))).count())
+        });
+        group.bench_with_input(BenchmarkId::new("without_protocol", name), &glob, |b, glob| {
+            b.iter(|| urls_without_protocol.iter().filter(|url| glob.is_match(black_box(url))).count())
+        });
+    }
+    group.finish();
+}
+
+/// A config with `count` ignore rules and as many profile rules, none of which match the corpus,
+/// so every URL goes through all of them.
+fn large_config(count: usize) -> AppConfig {
+    let ignored: Vec<String> = (0..count).map(|it| format!(r#""https://*.ads{it}.net/**""#)).collect();
+    let profile_rules: Vec<String> = (0..count)
+        .map(|it| format!(r#"{{ "pattern": "https://app{it}.corp.com/**", "profile": "Work" }}"#))
+        .collect();
+    let json = format!(
+        r#"{{ "ignored_urls": [{}], "profile_rules": [{}] }}"#,
+        ignored.join(","),
+        profile_rules.join(","),
+    );
+    serde_json::from_str(&json).unwrap()
+}
+
+fn bench_resolve(c: &mut Criterion) {
+    let urls = url_corpus();
+    let mut group = c.benchmark_group("resolve");
+    for count in [10, 100, 500] {
+        let config = large_config(count);
+        group.bench_with_input(BenchmarkId::from_parameter(count), &config, |b, config| {
+            b.iter(|| {
+                for url in &urls {
+                    black_box(resolve(black_box(url), config));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Like [`large_config`], with the rest of the batch filtering on top: an allowlist with a negated
+/// exception, URL length bounds and the deduplication (on by default).
+fn large_filtering_config(count: usize) -> AppConfig {
+    let ignored: Vec<String> = (0..count)
+        .map(|it| format!(r#""https://*.ads{it}.net/**""#))
+        .chain([r#""https://a.b.c.tracking.com/**""#.to_owned(), r#""!https://a.b.c.tracking.com/post""#.to_owned()])
+        .collect();
+    let profile_rules: Vec<String> = (0..count)
+        .map(|it| format!(r#"{{ "pattern": "https://app{it}.corp.com/**", "profile": "Work" }}"#))
+        .collect();
+    let json = format!(
+        r#"{{
+            "firefox_path": "/opt/firefox",
+            "ignored_urls": [{}],
+            "allowed_urls": ["https://**", "!https://example.com/caf%C3%A9"],
+            "min_url_len": 20,
+            "max_url_len": 60,
+            "profile_rules": [{}]
+        }}"#,
+        ignored.join(","),
+        profile_rules.join(","),
+    );
+    serde_json::from_str(&json).unwrap()
+}
+
+/// Drops the commands. Unlike the dry run of the tests' recording launcher, it prints nothing, and
+/// as Firefox is running nothing waits for it to start either.
+struct DiscardingLauncher;
+
+impl Launcher for DiscardingLauncher {
+    fn launch(&self, command: &mut Command) -> std::io::Result<()> {
+        black_box(command);
+        Ok(())
+    }
+}
+
+/// The whole `open_links` flow over a batch of URLs (with duplicates), the commands being dropped
+/// rather than run and the running Firefox taken from a fixed list.
+fn bench_open_links(c: &mut Criterion) {
+    let corpus = url_corpus();
+    let urls: Vec<String> = (0..10)
+        .flat_map(|it| corpus.iter().map(move |url| match it % 5 {
+            0 => url.clone(), // Duplicates of the corpus
+            _ => format!("{url}{}ref={it}", if url.contains('?') { '&' } else { '?' }),
+        }))
+        .collect();
+    let processes = FixedProcesses { processes: vec![FirefoxInfo::new("/running/firefox", Some("Personal"), 1)], frontmost: None };
+
+    let mut group = c.benchmark_group("open_links");
+    for count in [10, 100, 500] {
+        let config = large_filtering_config(count);
+        group.bench_with_input(BenchmarkId::new(format!("{}_urls", urls.len()), count), &config, |b, config| {
+            b.iter(|| open_links(black_box(urls.clone()), Some(config), &DiscardingLauncher, &processes).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_compile(c: &mut Criterion) {
+    let globs: Vec<String> = (0..500).map(|it| format!("https://*.site{it}.com/{{docs,blog}}/**")).collect();
+    c.bench_function("compile_500_globs", |b| b.iter(|| Glob::new_many(black_box(&globs)).unwrap()));
+}
+
+criterion_group!(benches, bench_glob, bench_resolve, bench_open_links, bench_compile);
+criterion_main!(benches);
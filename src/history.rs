@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Size past which the oldest half of the history is dropped, so it never grows unbounded.
+const MAX_HISTORY_SIZE: u64 = 256 * 1024;
+
+/// What was done with a URL passed to FirefoxRouter, one per line of the opt-in `history_file`, as
+/// each invocation is a separate short-lived process.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HistoryEntry {
+    /// When the URL was routed, in RFC 3339 local time.
+    pub time: String,
+    /// The URL as it was opened, after the rewrite rules, or as it was passed when filtered out (once
+    /// local paths and bare hosts were turned into URLs).
+    pub url: String,
+    /// The rule that decided the outcome (e.g. `profile_rules[1]`), if any.
+    pub rule: Option<String>,
+    /// The profile the URL was opened in, when it was opened in Firefox with a known profile.
+    pub profile: Option<String>,
+    pub outcome: HistoryOutcome,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryOutcome {
+    Firefox,
+    /// Filtered out by the ignore or allow rules, or by a scheme rule.
+    Ignored,
+    /// Handed to another browser by a browser rule.
+    Browser,
+    /// Handed to the system's default browser or mail client.
+    DefaultBrowser,
+    /// Couldn't be opened, as launching the browser failed.
+    Failed,
+}
+
+impl HistoryEntry {
+    pub fn new(url: String, rule: Option<String>, profile: Option<String>, outcome: HistoryOutcome) -> Self {
+        let time = chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%:z").to_string();
+        Self { time, url, rule, profile, outcome }
+    }
+}
+
+impl std::fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} -> ", self.time, self.url)?;
+        match (self.outcome, &self.profile) {
+            (HistoryOutcome::Firefox, Some(profile)) => write!(f, "Firefox, profile '{profile}'")?,
+            (HistoryOutcome::Firefox, None) => write!(f, "Firefox, default profile")?,
+            (HistoryOutcome::Ignored, _) => write!(f, "ignored")?,
+            (HistoryOutcome::Browser, _) => write!(f, "another browser")?,
+            (HistoryOutcome::DefaultBrowser, _) => write!(f, "default browser")?,
+            (HistoryOutcome::Failed, _) => write!(f, "failed to open")?,
+        }
+        match &self.rule {
+            Some(rule) => write!(f, " ({rule})"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Appends `entries` to the history at `path`, first dropping its oldest half once it's past
+/// [`MAX_HISTORY_SIZE`].
+pub fn append(path: &Path, entries: &[HistoryEntry]) -> io::Result<()> {
+    append_capped(path, entries, MAX_HISTORY_SIZE)
+}
+
+fn append_capped(path: &Path, entries: &[HistoryEntry], max_size: u64) -> io::Result<()> {
+    if fs::metadata(path).is_ok_and(|it| it.len() >= max_size) {
+        let contents = fs::read_to_string(path)?;
+        let lines: Vec<&str> = contents.lines().collect();
+        let kept = &lines[lines.len() / 2..];
+        fs::write(path, kept.iter().map(|it| format!("{it}\n")).collect::<String>())?;
+    }
+    let mut contents = String::new();
+    for entry in entries {
+        contents.push_str(&serde_json::to_string(entry)?);
+        contents.push('\n');
+    }
+    File::options().append(true).create(true).open(path)?.write_all(contents.as_bytes())
+}
+
+/// The last `count` entries of the history at `path`, oldest first, skipping the lines that don't
+/// parse. A missing file is an empty history.
+pub fn read_last(path: &Path, count: usize) -> io::Result<Vec<HistoryEntry>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let entries: Vec<HistoryEntry> = contents.lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    let skipped = entries.len().saturating_sub(count);
+    Ok(entries.into_iter().skip(skipped).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_folder;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        test_folder(name).join("FirefoxRouter.history.jsonl")
+    }
+
+    fn entry(url: &str) -> HistoryEntry {
+        HistoryEntry::new(url.to_owned(), Some("profile_rules[0]".to_owned()), Some("Work".to_owned()), HistoryOutcome::Firefox)
+    }
+
+    #[test]
+    fn entries_are_read_back() {
+        let path = temp_path("history-read");
+        assert_eq!(read_last(&path, 10).unwrap(), []);
+        let ignored = HistoryEntry::new("https://ads.com".to_owned(), Some("ignored_urls[0]".to_owned()), None, HistoryOutcome::Ignored);
+        append(&path, &[entry("https://a.com"), ignored.clone()]).unwrap();
+        append(&path, &[entry("https://b.com")]).unwrap();
+
+        // The entries may have been written on different seconds
+        let without_time = |it: HistoryEntry| HistoryEntry { time: String::new(), ..it };
+        let entries: Vec<HistoryEntry> = read_last(&path, 10).unwrap().into_iter().map(without_time).collect();
+        assert_eq!(entries, [entry("https://a.com"), ignored, entry("https://b.com")].map(without_time));
+        let last = read_last(&path, 2).unwrap();
+        assert_eq!(last.iter().map(|it| it.url.as_str()).collect::<Vec<_>>(), ["https://ads.com", "https://b.com"]);
+        assert_eq!(last[0].outcome, HistoryOutcome::Ignored);
+        assert_eq!(last[1].profile.as_deref(), Some("Work"));
+    }
+
+    #[test]
+    fn unreadable_lines_are_skipped() {
+        let path = temp_path("history-garbage");
+        fs::write(&path, "not json\n").unwrap();
+        append(&path, &[entry("https://a.com")]).unwrap();
+        assert_eq!(read_last(&path, 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn oldest_half_is_dropped_when_full() {
+        let path = temp_path("history-cap");
+        for index in 0..10 {
+            append_capped(&path, &[entry(&format!("https://{index}.com"))], 1000).unwrap();
+        }
+        assert!(fs::metadata(&path).unwrap().len() < 1000 + 200);
+        let urls: Vec<String> = read_last(&path, 100).unwrap().into_iter().map(|it| it.url).collect();
+        assert_eq!(urls.last().map(String::as_str), Some("https://9.com"));
+        assert!(!urls.contains(&"https://0.com".to_owned()), "Oldest entries should be dropped: {urls:?}");
+    }
+
+    #[test]
+    fn entry_is_displayed_on_one_line() {
+        let mut entry = entry("https://a.com");
+        entry.time = "2024-01-02T03:04:05+00:00".to_owned();
+        assert_eq!(entry.to_string(), "2024-01-02T03:04:05+00:00 https://a.com -> Firefox, profile 'Work' (profile_rules[0])");
+    }
+}
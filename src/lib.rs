@@ -0,0 +1,19 @@
+//! The URL routing engine of FirefoxRouter: the config, the URL globs and the decision of where each
+//! URL is opened, which the binary is a thin wrapper around.
+
+#[macro_use]
+mod log_macro;
+pub mod config;
+pub mod glob;
+mod history;
+mod log_file;
+mod profile_usage;
+mod profiles;
+mod registration;
+mod router;
+mod util;
+mod win;
+
+pub use config::{read_app_config, AppConfig};
+pub use glob::Glob;
+pub use router::{open_links, profile_for, resolve, run, Decision, FirefoxInfo, Launcher, ProcessSource, ProfilePool, Route};
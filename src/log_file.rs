@@ -0,0 +1,148 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Env variable with the path of the log file, taking precedence over the `logging` config block.
+pub const LOG_FILE_ENV_VAR: &str = "FIREFOX_ROUTER_LOG_FILE";
+/// Size at which the log file is rotated.
+const MAX_LOG_SIZE: u64 = 1024 * 1024;
+/// How many rotated files (`.1` being the most recent) are kept besides the current one.
+const KEPT_LOG_FILES: usize = 3;
+
+static LOG_FILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Starts appending the output of the log macros to `path`.
+pub fn enable(path: PathBuf) {
+    *LOG_FILE.lock().unwrap_or_else(|it| it.into_inner()) = Some(path);
+}
+
+/// Enables the log file if the env variable is set, returning whether it was.
+pub fn enable_from_env() -> bool {
+    let Some(path) = std::env::var_os(LOG_FILE_ENV_VAR).filter(|it| !it.is_empty()) else {
+        return false;
+    };
+    enable(PathBuf::from(path));
+    true
+}
+
+/// Stops writing to the log file, returning its path if it was enabled.
+pub fn disable() -> Option<PathBuf> {
+    LOG_FILE.lock().unwrap_or_else(|it| it.into_inner()).take()
+}
+
+pub fn is_enabled() -> bool {
+    LOG_FILE.lock().unwrap_or_else(|it| it.into_inner()).is_some()
+}
+
+/// Appends `message` to the log file, if enabled. Failing to write is silently ignored, as there's
+/// nowhere left to report it.
+pub fn write(message: &str) {
+    let log_file = LOG_FILE.lock().unwrap_or_else(|it| it.into_inner());
+    if let Some(path) = log_file.as_ref() {
+        let _ = append(path, message, MAX_LOG_SIZE, KEPT_LOG_FILES);
+    }
+}
+
+/// Writes each line of `message` prefixed with the current time, rotating the file first if it
+/// reached `max_size`.
+fn append(path: &Path, message: &str, max_size: u64, kept_files: usize) -> io::Result<()> {
+    if fs::metadata(path).is_ok_and(|it| it.len() >= max_size) {
+        rotate(path, kept_files)?;
+    }
+    let mut file = File::options().append(true).create(true).open(path)?;
+    let time = chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%.3f%:z");
+    let mut contents = String::with_capacity(message.len() + 32);
+    for line in message.lines() {
+        contents.push_str(&format!("[{time}] {line}\n"));
+    }
+    file.write_all(contents.as_bytes())
+}
+
+/// Shifts `path.1` to `path.2` and so on, dropping the oldest file, then moves `path` to `path.1`.
+fn rotate(path: &Path, kept_files: usize) -> io::Result<()> {
+    if kept_files == 0 {
+        return ignore_not_found(fs::remove_file(path));
+    }
+    ignore_not_found(fs::remove_file(rotated_path(path, kept_files)))?;
+    for index in (1..kept_files).rev() {
+        ignore_not_found(fs::rename(rotated_path(path, index), rotated_path(path, index + 1)))?;
+    }
+    fs::rename(path, rotated_path(path, 1))
+}
+
+/// The log file at `path` followed by the rotated files it may have.
+pub fn log_file_paths(path: &Path) -> Vec<PathBuf> {
+    std::iter::once(path.to_path_buf())
+        .chain((1..=KEPT_LOG_FILES).map(|index| rotated_path(path, index)))
+        .collect()
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{index}"));
+    PathBuf::from(rotated)
+}
+
+fn ignore_not_found(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_folder;
+
+    #[test]
+    fn append_prefixes_each_line_with_time() {
+        let path = test_folder("log-append").join("FirefoxRouter.log");
+        append(&path, "first", MAX_LOG_SIZE, KEPT_LOG_FILES).unwrap();
+        append(&path, "second\nthird", MAX_LOG_SIZE, KEPT_LOG_FILES).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines.iter().all(|it| it.starts_with('[')), "Lines should start with the time: {contents}");
+        assert!(lines[0].ends_with("] first"));
+        assert!(lines[2].ends_with("] third"));
+    }
+
+    #[test]
+    fn append_rotates_when_file_is_full() {
+        let path = test_folder("log-rotate").join("FirefoxRouter.log");
+        for index in 0..6 {
+            append(&path, &format!("message {index}"), 1, 2).unwrap();
+        }
+
+        let read = |path: &Path| fs::read_to_string(path).unwrap();
+        assert!(read(&path).ends_with("] message 5\n"));
+        assert!(read(&rotated_path(&path, 1)).ends_with("] message 4\n"));
+        assert!(read(&rotated_path(&path, 2)).ends_with("] message 3\n"));
+        assert!(!rotated_path(&path, 3).exists(), "Only 2 rotated files should be kept");
+    }
+
+    #[test]
+    fn log_file_paths_include_rotated_files() {
+        let paths = log_file_paths(Path::new("FirefoxRouter.log"));
+        assert_eq!(paths.len(), KEPT_LOG_FILES + 1);
+        assert_eq!(paths[0], Path::new("FirefoxRouter.log"));
+        assert_eq!(paths[1], Path::new("FirefoxRouter.log.1"));
+    }
+
+    #[test]
+    fn append_keeps_small_files() {
+        let path = test_folder("log-small").join("FirefoxRouter.log");
+        append(&path, "first", MAX_LOG_SIZE, KEPT_LOG_FILES).unwrap();
+        append(&path, "second", MAX_LOG_SIZE, KEPT_LOG_FILES).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+        assert!(!rotated_path(&path, 1).exists());
+    }
+
+    #[test]
+    fn rotated_path_appends_index() {
+        assert_eq!(rotated_path(Path::new("logs/FirefoxRouter.log"), 2), Path::new("logs/FirefoxRouter.log.2"));
+    }
+}
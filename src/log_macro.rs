@@ -1,14 +1,162 @@
-// Disable warnings
-#[allow(unused_macros)]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+/// Env variable with the minimum level of the messages printed, e.g. `info` or `debug`.
+pub const LOG_LEVEL_ENV_VAR: &str = "FIREFOXROUTER_LOG";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "error" => Some(Self::Error),
+            "warn" | "warning" => Some(Self::Warn),
+            "info" => Some(Self::Info),
+            "debug" => Some(Self::Debug),
+            _ => None,
+        }
+    }
+
+    /// Prefix of the message when printed to the console.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Self::Error => "Error: ",
+            Self::Warn => "Warning: ",
+            Self::Info | Self::Debug => "",
+        }
+    }
+
+    /// Label of the message in the log file.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Error => "ERROR",
+            Self::Warn => "WARN",
+            Self::Info => "INFO",
+            Self::Debug => "DEBUG",
+        }
+    }
+}
+
+/// `debug` in debug builds, `warn` in release builds, so `debug!` messages are only printed in
+/// release when the env variable asks for them.
+fn default_threshold() -> LogLevel {
+    if cfg!(debug_assertions) { LogLevel::Debug } else { LogLevel::Warn }
+}
+
+fn threshold_from(value: Option<&str>) -> LogLevel {
+    value.and_then(LogLevel::parse).unwrap_or_else(default_threshold)
+}
+
+/// Whether messages of `level` are printed to the console, read once from the env variable.
+pub fn is_level_enabled(level: LogLevel) -> bool {
+    static THRESHOLD: OnceLock<LogLevel> = OnceLock::new();
+    level <= *THRESHOLD.get_or_init(|| threshold_from(std::env::var(LOG_LEVEL_ENV_VAR).ok().as_deref()))
+}
 
+static DIAGNOSTICS_ON_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Prints every leveled message to stderr from now on, leaving stdout to the output of commands
+/// read by scripts, such as `--profile-for`.
+pub fn print_diagnostics_to_stderr() {
+    DIAGNOSTICS_ON_STDERR.store(true, Ordering::Relaxed);
+}
+
+/// Whether a message of `level` printed to the console goes to stderr: warnings and errors always
+/// do, the others after [`print_diagnostics_to_stderr`].
+pub fn is_printed_to_stderr(level: LogLevel) -> bool {
+    level <= LogLevel::Warn || DIAGNOSTICS_ON_STDERR.load(Ordering::Relaxed)
+}
+
+/// Prints a message regardless of the log level, used for the output of the commands.
+#[allow(unused_macros)]
 macro_rules! log {
-    ($( $args:expr ),*) => { println!( $( $args ),* ); }
+    ($( $args:expr ),*) => {{
+        let message = format!( $( $args ),* );
+        println!("{message}");
+        crate::log_file::write(&message);
+    }}
 }
 
-macro_rules! debug_log {
-    ($( $args:expr ),*) => {
-        if cfg!(debug_assertions) {
-            println!( $( $args ),* );
+/// Prints the message if `level` passes the threshold. The log file, when enabled, gets every
+/// message no matter the threshold, so routing decisions can be diagnosed in release builds too.
+macro_rules! log_at {
+    ($level:expr, $( $args:expr ),*) => {{
+        let level: crate::log_macro::LogLevel = $level;
+        let to_console = crate::log_macro::is_level_enabled(level);
+        if to_console || crate::log_file::is_enabled() {
+            let message = format!( $( $args ),* );
+            if to_console && crate::log_macro::is_printed_to_stderr(level) {
+                eprintln!("{}{message}", level.prefix());
+            } else if to_console {
+                println!("{}{message}", level.prefix());
+            }
+            crate::log_file::write(&format!("{} {message}", level.label()));
         }
+    }}
+}
+
+#[allow(unused_macros)]
+macro_rules! error {
+    ($( $args:expr ),*) => { log_at!(crate::log_macro::LogLevel::Error, $( $args ),*) }
+}
+
+#[allow(unused_macros)]
+macro_rules! warn {
+    ($( $args:expr ),*) => { log_at!(crate::log_macro::LogLevel::Warn, $( $args ),*) }
+}
+
+#[allow(unused_macros)]
+macro_rules! info {
+    ($( $args:expr ),*) => { log_at!(crate::log_macro::LogLevel::Info, $( $args ),*) }
+}
+
+macro_rules! debug {
+    ($( $args:expr ),*) => { log_at!(crate::log_macro::LogLevel::Debug, $( $args ),*) }
+}
+
+/// Kept for the existing call sites, same as `debug!`.
+macro_rules! debug_log {
+    ($( $args:expr ),*) => { debug!($( $args ),*) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_are_ordered_by_verbosity() {
+        assert!(LogLevel::Error < LogLevel::Warn);
+        assert!(LogLevel::Warn < LogLevel::Info);
+        assert!(LogLevel::Info < LogLevel::Debug);
+    }
+
+    #[test]
+    fn threshold_parses_env_value() {
+        assert_eq!(threshold_from(Some("info")), LogLevel::Info);
+        assert_eq!(threshold_from(Some(" DEBUG ")), LogLevel::Debug);
+        assert_eq!(threshold_from(Some("warning")), LogLevel::Warn);
+        assert_eq!(threshold_from(Some("error")), LogLevel::Error);
+    }
+
+    #[test]
+    fn diagnostics_can_be_moved_to_stderr() {
+        assert!(is_printed_to_stderr(LogLevel::Error));
+        assert!(is_printed_to_stderr(LogLevel::Warn));
+        print_diagnostics_to_stderr();
+        assert!(is_printed_to_stderr(LogLevel::Info));
+        assert!(is_printed_to_stderr(LogLevel::Debug));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn threshold_defaults_per_build() {
+        let expected = if cfg!(debug_assertions) { LogLevel::Debug } else { LogLevel::Warn };
+        assert_eq!(threshold_from(None), expected);
+        assert_eq!(threshold_from(Some("verbose")), expected);
+    }
+}
@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// When each profile of a profile pool was last picked (in milliseconds since the Unix epoch), kept in
+/// a `FirefoxRouter.usage.json` file next to the config so it survives between invocations.
+pub type ProfileUsage = HashMap<String, u64>;
+
+pub fn profile_usage_path(config_path: &Path) -> PathBuf {
+    config_path.with_extension("usage.json")
+}
+
+/// A missing or unreadable file means no profile was picked yet.
+pub fn read_profile_usage(path: &Path) -> ProfileUsage {
+    fs::read_to_string(path).ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Records that `profile` was just picked. Failing to do so only makes the next pick less even, so
+/// errors are just logged.
+pub fn record_profile_use(path: &Path, profile: &str) {
+    let mut usage = read_profile_usage(path);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+    usage.insert(profile.to_owned(), now);
+    let result = serde_json::to_string(&usage)
+        .map_err(std::io::Error::from)
+        .and_then(|contents| fs::write(path, contents));
+    if let Err(e) = result {
+        debug_log!("Couldn't save the profile usage to {}: {e}", path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_folder;
+
+    #[test]
+    fn profile_usage_path_is_next_to_config() {
+        assert_eq!(profile_usage_path(Path::new("dir/FirefoxRouter.json")), Path::new("dir/FirefoxRouter.usage.json"));
+    }
+
+    #[test]
+    fn record_profile_use_keeps_other_profiles() {
+        let path = test_folder("usage").join("FirefoxRouter.usage.json");
+        assert!(read_profile_usage(&path).is_empty());
+
+        record_profile_use(&path, "Research1");
+        record_profile_use(&path, "Research2");
+        let usage = read_profile_usage(&path);
+        assert_eq!(usage.len(), 2);
+        assert!(usage["Research1"] <= usage["Research2"]);
+    }
+}
@@ -0,0 +1,267 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FirefoxProfile {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Default)]
+struct ProfileSection {
+    name: Option<String>,
+    path: Option<String>,
+    is_relative: bool,
+    is_default: bool,
+}
+
+/// Reads Firefox's `profiles.ini` and returns the profile Firefox would open when launched without
+/// a profile argument, or `None` if the file is missing or doesn't specify a default.
+pub fn find_default_profile() -> Option<FirefoxProfile> {
+    let Some(ini_path) = profiles_ini_path() else {
+        debug_log!("Couldn't determine the location of profiles.ini");
+        return None;
+    };
+    let contents = match fs::read_to_string(&ini_path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            debug_log!("Couldn't read profiles.ini at {}: {}", ini_path.display(), e);
+            return None;
+        }
+    };
+    let base_dir = ini_path.parent().unwrap_or(Path::new(""));
+    parse_default_profile(&contents, base_dir)
+}
+
+#[cfg(windows)]
+fn profiles_ini_path() -> Option<PathBuf> {
+    std::env::var_os("APPDATA")
+        .map(|it| PathBuf::from(it).join("Mozilla").join("Firefox").join("profiles.ini"))
+}
+
+#[cfg(not(windows))]
+fn profiles_ini_path() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .map(|it| PathBuf::from(it).join(".mozilla").join("firefox").join("profiles.ini"))
+}
+
+/// Reads Firefox's `profiles.ini` and returns every profile it lists, or none if it's missing.
+pub fn list_profiles() -> Vec<FirefoxProfile> {
+    let Some(ini_path) = profiles_ini_path() else {
+        return Vec::new();
+    };
+    let Ok(contents) = fs::read_to_string(&ini_path) else {
+        debug_log!("Couldn't read profiles.ini at {}", ini_path.display());
+        return Vec::new();
+    };
+    let base_dir = ini_path.parent().unwrap_or(Path::new(""));
+    let (_, profiles) = parse_sections(&contents);
+    profiles.iter().filter_map(|it| it.to_profile(base_dir)).collect()
+}
+
+/// Returns the profile whose lock is held by the Firefox process `pid`, for Firefox processes started
+/// without a profile argument. On Linux, the `lock` symlink of a profile points to `<ip>:+<pid>` of
+/// its owner. On Windows, `parent.lock` only tells whether a profile is in use, so the profile is
+/// only found when it's the only one in use.
+#[cfg(windows)]
+pub fn locked_profile(_pid: u32, profiles: &[FirefoxProfile]) -> Option<&FirefoxProfile> {
+    let mut locked = profiles.iter().filter(|it| is_parent_lock_held(&it.path));
+    let profile = locked.next()?;
+    locked.next().is_none().then_some(profile)
+}
+
+#[cfg(not(windows))]
+pub fn locked_profile(pid: u32, profiles: &[FirefoxProfile]) -> Option<&FirefoxProfile> {
+    profiles.iter().find(|it| {
+        fs::read_link(it.path.join("lock"))
+            .is_ok_and(|target| parse_lock_pid(&target.to_string_lossy()) == Some(pid))
+    })
+}
+
+/// Firefox opens `parent.lock` without sharing it, so failing to open it with a sharing violation
+/// means a Firefox is using the profile.
+#[cfg(windows)]
+fn is_parent_lock_held(profile_dir: &Path) -> bool {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    fs::OpenOptions::new()
+        .read(true)
+        .share_mode(0)
+        .open(profile_dir.join("parent.lock"))
+        .is_err_and(|e| e.raw_os_error() == Some(ERROR_SHARING_VIOLATION))
+}
+
+/// Parses the PID out of the `<ip>:+<pid>` target of a profile's `lock` symlink.
+#[cfg(not(windows))]
+fn parse_lock_pid(target: &str) -> Option<u32> {
+    target.rsplit_once(":+")?.1.parse().ok()
+}
+
+/// The `[Install...]` sections hold the default profile of each Firefox installation and take
+/// precedence over the legacy `Default=1` flag of the `[Profile...]` sections.
+fn parse_default_profile(contents: &str, base_dir: &Path) -> Option<FirefoxProfile> {
+    let (install_defaults, profiles) = parse_sections(contents);
+    let default_profile = install_defaults.iter()
+        .find_map(|default_path| profiles.iter().find(|it| it.path.as_deref() == Some(default_path)))
+        .or_else(|| profiles.iter().find(|it| it.is_default))?;
+    default_profile.to_profile(base_dir)
+}
+
+/// Returns the default profile path of each `[Install...]` section along with the `[Profile...]` sections.
+fn parse_sections(contents: &str) -> (Vec<String>, Vec<ProfileSection>) {
+    let mut install_defaults = Vec::new();
+    let mut profiles: Vec<ProfileSection> = Vec::new();
+    let mut section = "";
+
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|it| it.strip_suffix(']')) {
+            section = name;
+            if section.starts_with("Profile") {
+                profiles.push(ProfileSection::default());
+            }
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+
+        if section.starts_with("Install") {
+            if key == "Default" {
+                install_defaults.push(value.to_owned());
+            }
+        } else if section.starts_with("Profile") {
+            let profile = profiles.last_mut().unwrap();
+            match key {
+                "Name" => profile.name = Some(value.to_owned()),
+                "Path" => profile.path = Some(value.to_owned()),
+                "IsRelative" => profile.is_relative = value == "1",
+                "Default" => profile.is_default = value == "1",
+                _ => {}
+            }
+        }
+    }
+    (install_defaults, profiles)
+}
+
+impl ProfileSection {
+    fn to_profile(&self, base_dir: &Path) -> Option<FirefoxProfile> {
+        let name = self.name.clone()?;
+        let path = self.path.as_deref()?;
+        let path = if self.is_relative {
+            base_dir.join(path)
+        } else {
+            PathBuf::from(path)
+        };
+        Some(FirefoxProfile { name, path })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> Option<FirefoxProfile> {
+        parse_default_profile(contents, Path::new("base"))
+    }
+
+    #[test]
+    fn install_default_takes_precedence() {
+        let profile = parse("
+            [Install308046B0AF4A39CB]
+            Default=Profiles/abc.work
+            Locked=1
+
+            [Profile1]
+            Name=work
+            IsRelative=1
+            Path=Profiles/abc.work
+
+            [Profile0]
+            Name=default
+            IsRelative=1
+            Path=Profiles/xyz.default
+            Default=1
+        ").unwrap();
+        assert_eq!(profile.name, "work");
+        assert_eq!(profile.path, Path::new("base").join("Profiles/abc.work"));
+    }
+
+    #[test]
+    fn legacy_default_flag_is_fallback() {
+        let profile = parse("
+            [General]
+            StartWithLastProfile=1
+
+            [Profile0]
+            Name=other
+            IsRelative=1
+            Path=Profiles/other
+
+            [Profile1]
+            Name=default
+            IsRelative=1
+            Path=Profiles/xyz.default
+            Default=1
+        ").unwrap();
+        assert_eq!(profile.name, "default");
+    }
+
+    #[test]
+    fn absolute_profile_path() {
+        let profile = parse("
+            [Profile0]
+            Name=default
+            IsRelative=0
+            Path=/somewhere/else
+            Default=1
+        ").unwrap();
+        assert_eq!(profile.path, PathBuf::from("/somewhere/else"));
+    }
+
+    #[test]
+    fn no_default_profile() {
+        assert_eq!(parse("
+            [Profile0]
+            Name=default
+            IsRelative=1
+            Path=Profiles/xyz.default
+        "), None);
+    }
+
+    #[test]
+    fn empty_file() {
+        assert_eq!(parse(""), None);
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn lock_pid_is_parsed() {
+        assert_eq!(parse_lock_pid("127.0.1.1:+4321"), Some(4321));
+        assert_eq!(parse_lock_pid("fe80::1:+12"), Some(12));
+        assert_eq!(parse_lock_pid("garbage"), None);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn locked_profile_matches_lock_owner() {
+        let folder = crate::util::test_folder("lock");
+        let (work, other) = (folder.join("work"), folder.join("other"));
+        fs::create_dir_all(&work).unwrap();
+        fs::create_dir_all(&other).unwrap();
+        std::os::unix::fs::symlink("127.0.1.1:+4321", work.join("lock")).unwrap();
+        std::os::unix::fs::symlink("127.0.1.1:+99", other.join("lock")).unwrap();
+
+        let profiles = [
+            FirefoxProfile { name: "other".to_owned(), path: other },
+            FirefoxProfile { name: "work".to_owned(), path: work },
+        ];
+        assert_eq!(locked_profile(4321, &profiles).map(|it| it.name.as_str()), Some("work"));
+        assert_eq!(locked_profile(1, &profiles), None);
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
@@ -0,0 +1,306 @@
+#[cfg(any(windows, target_os = "linux"))]
+use crate::util::get_current_exe_path;
+use color_eyre::Result;
+
+/// The ID the OS reports as the default handler of `http(s)` links when the router is the default
+/// browser: the URL ProgID on Windows, the desktop entry on Linux.
+#[cfg(windows)]
+pub const URL_HANDLER_ID: &str = "FirefoxRouterURL";
+#[cfg(target_os = "linux")]
+pub const URL_HANDLER_ID: &str = DESKTOP_FILE_NAME;
+#[cfg(not(any(windows, target_os = "linux")))]
+pub const URL_HANDLER_ID: &str = "";
+
+#[cfg(windows)]
+pub fn register() -> Result<()> {
+    use winreg::enums::HKEY_CURRENT_USER;
+    use winreg::RegKey;
+
+    register_in(RegKey::predef(HKEY_CURRENT_USER))?;
+    log!("FirefoxRouter registered as a browser. Open Settings > Default Apps to set it as default");
+    Ok(())
+}
+
+/// Registers the router for every user of the machine, which requires running elevated.
+#[cfg(windows)]
+pub fn register_machine() -> Result<()> {
+    use color_eyre::eyre::WrapErr;
+    use winreg::enums::HKEY_LOCAL_MACHINE;
+    use winreg::RegKey;
+
+    register_in(RegKey::predef(HKEY_LOCAL_MACHINE))
+        .wrap_err("Couldn't register for all users, machine-wide registration must be run as administrator")?;
+    log!("FirefoxRouter registered as a browser for all users. Open Settings > Default Apps to set it as default");
+    Ok(())
+}
+
+/// Writes the ProgIDs, the `StartMenuInternet` client and the `RegisteredApplications` entry under
+/// `root`, which is either `HKEY_CURRENT_USER` or `HKEY_LOCAL_MACHINE`.
+#[cfg(windows)]
+fn register_in(root: winreg::RegKey) -> Result<()> {
+    unregister_in(&root);
+
+    let exe_path = get_current_exe_path().to_string_lossy().into_owned();
+
+    log!("Current exe path: {exe_path}");
+
+    // ProgID for URL handling
+    let (url_class, _) = root.create_subkey(r"SOFTWARE\Classes\FirefoxRouterURL")?;
+    url_class.set_value("", &"FirefoxRouter URL")?;
+    url_class.set_value("URL Protocol", &"")?;
+    let (url_icon, _) = root.create_subkey(r"SOFTWARE\Classes\FirefoxRouterURL\DefaultIcon")?;
+    url_icon.set_value("", &format!("{exe_path},0"))?;
+    let (url_cmd, _) = root.create_subkey(r"SOFTWARE\Classes\FirefoxRouterURL\shell\open\command")?;
+    // Quoting `%1` keeps URLs with spaces in a single argument, which is then passed on to Firefox as is
+    url_cmd.set_value("", &format!("\"{exe_path}\" \"%1\""))?;
+
+    // ProgID for HTML file handling
+    let (html_class, _) = root.create_subkey(r"SOFTWARE\Classes\FirefoxRouterHTML")?;
+    html_class.set_value("", &"FirefoxRouter HTML Document")?;
+    let (html_icon, _) = root.create_subkey(r"SOFTWARE\Classes\FirefoxRouterHTML\DefaultIcon")?;
+    html_icon.set_value("", &format!("{exe_path},1"))?;
+    let (html_cmd, _) = root.create_subkey(r"SOFTWARE\Classes\FirefoxRouterHTML\shell\open\command")?;
+    html_cmd.set_value("", &format!("\"{exe_path}\" \"%1\""))?;
+
+    // ProgID for PDF file handling, so PDFs open in Firefox's built-in viewer
+    let (pdf_class, _) = root.create_subkey(r"SOFTWARE\Classes\FirefoxRouterPDF")?;
+    pdf_class.set_value("", &"FirefoxRouter PDF Document")?;
+    let (pdf_icon, _) = root.create_subkey(r"SOFTWARE\Classes\FirefoxRouterPDF\DefaultIcon")?;
+    pdf_icon.set_value("", &format!("{exe_path},2"))?;
+    let (pdf_cmd, _) = root.create_subkey(r"SOFTWARE\Classes\FirefoxRouterPDF\shell\open\command")?;
+    pdf_cmd.set_value("", &format!("\"{exe_path}\" \"%1\""))?;
+
+    // StartMenuInternet client
+    let (client, _) = root.create_subkey(r"SOFTWARE\Clients\StartMenuInternet\FirefoxRouter")?;
+    client.set_value("", &"Firefox Router")?;
+    let (caps, _) = root.create_subkey(r"SOFTWARE\Clients\StartMenuInternet\FirefoxRouter\Capabilities")?;
+    caps.set_value("ApplicationName", &"Firefox Router")?;
+    caps.set_value("ApplicationDescription", &"Routes URLs to Firefox using the active profile")?;
+    let (file_assoc, _) = root.create_subkey(r"SOFTWARE\Clients\StartMenuInternet\FirefoxRouter\Capabilities\FileAssociations")?;
+    file_assoc.set_value(".htm", &"FirefoxRouterHTML")?;
+    file_assoc.set_value(".html", &"FirefoxRouterHTML")?;
+    file_assoc.set_value(".pdf", &"FirefoxRouterPDF")?;
+    let (start_menu, _) = root.create_subkey(r"SOFTWARE\Clients\StartMenuInternet\FirefoxRouter\Capabilities\StartMenu")?;
+    start_menu.set_value("StartMenuInternet", &"FirefoxRouter")?;
+    let (url_assoc, _) = root.create_subkey(r"SOFTWARE\Clients\StartMenuInternet\FirefoxRouter\Capabilities\URLAssociations")?;
+    url_assoc.set_value("http", &"FirefoxRouterURL")?;
+    url_assoc.set_value("https", &"FirefoxRouterURL")?;
+    let (client_icon, _) = root.create_subkey(r"SOFTWARE\Clients\StartMenuInternet\FirefoxRouter\DefaultIcon")?;
+    client_icon.set_value("", &format!("{exe_path},0"))?;
+    let (client_cmd, _) = root.create_subkey(r"SOFTWARE\Clients\StartMenuInternet\FirefoxRouter\shell\open\command")?;
+    client_cmd.set_value("", &format!("\"{exe_path}\""))?;
+
+    // RegisteredApplications entry
+    let (reg_apps, _) = root.create_subkey(r"SOFTWARE\RegisteredApplications")?;
+    reg_apps.set_value("FirefoxRouter", &r"SOFTWARE\Clients\StartMenuInternet\FirefoxRouter\Capabilities")?;
+    Ok(())
+}
+
+/// Removes the registration from both the user and the machine hives. Cleaning the machine hive
+/// needs elevation, so it silently leaves the machine-wide registration in place otherwise.
+#[cfg(windows)]
+pub fn unregister() -> Result<()> {
+    use winreg::enums::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE};
+    use winreg::RegKey;
+
+    unregister_in(&RegKey::predef(HKEY_CURRENT_USER));
+    unregister_in(&RegKey::predef(HKEY_LOCAL_MACHINE));
+
+    log!("FirefoxRouter unregistered");
+    Ok(())
+}
+
+#[cfg(windows)]
+fn unregister_in(root: &winreg::RegKey) {
+    use winreg::enums::KEY_ALL_ACCESS;
+
+    // Remove ProgIDs
+    let _ = root.delete_subkey_all(r"SOFTWARE\Classes\FirefoxRouterURL");
+    let _ = root.delete_subkey_all(r"SOFTWARE\Classes\FirefoxRouterHTML");
+    let _ = root.delete_subkey_all(r"SOFTWARE\Classes\FirefoxRouterPDF");
+
+    // Remove StartMenuInternet client
+    let _ = root.delete_subkey_all(r"SOFTWARE\Clients\StartMenuInternet\FirefoxRouter");
+
+    // Remove RegisteredApplications entry
+    if let Ok(reg_apps) = root.open_subkey_with_flags(r"SOFTWARE\RegisteredApplications", KEY_ALL_ACCESS) {
+        let _ = reg_apps.delete_value("FirefoxRouter");
+    }
+}
+
+/// Whether the `RegisteredApplications` entry exists, in either the user or the machine hive.
+#[cfg(windows)]
+pub fn is_registered_application() -> bool {
+    use winreg::enums::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE};
+    use winreg::RegKey;
+
+    [HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE].into_iter().any(|root| {
+        RegKey::predef(root).open_subkey(r"SOFTWARE\RegisteredApplications")
+            .and_then(|it| it.get_value::<String, _>("FirefoxRouter"))
+            .is_ok()
+    })
+}
+
+/// Whether the `StartMenuInternet` client key exists, in either the user or the machine hive.
+#[cfg(windows)]
+pub fn has_start_menu_client() -> bool {
+    use winreg::enums::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE};
+    use winreg::RegKey;
+
+    [HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE].into_iter().any(|root| {
+        RegKey::predef(root).open_subkey(r"SOFTWARE\Clients\StartMenuInternet\FirefoxRouter").is_ok()
+    })
+}
+
+/// The ProgID the user picked as the default handler of `scheme` links.
+#[cfg(windows)]
+pub fn default_handler(scheme: &str) -> Option<String> {
+    use winreg::enums::HKEY_CURRENT_USER;
+    use winreg::RegKey;
+
+    let path = format!(r"SOFTWARE\Microsoft\Windows\Shell\Associations\UrlAssociations\{scheme}\UserChoice");
+    RegKey::predef(HKEY_CURRENT_USER).open_subkey(path)
+        .and_then(|it| it.get_value::<String, _>("ProgId"))
+        .ok()
+}
+
+#[cfg(target_os = "linux")]
+const DESKTOP_FILE_NAME: &str = "firefox-router.desktop";
+
+/// On Linux the desktop entry is both the application registration and the browser client.
+#[cfg(target_os = "linux")]
+pub fn is_registered_application() -> bool {
+    desktop_file_path().is_ok_and(|it| it.is_file())
+}
+
+#[cfg(target_os = "linux")]
+pub fn has_start_menu_client() -> bool {
+    is_registered_application()
+}
+
+/// The desktop entry handling `scheme` links, as reported by `xdg-mime`.
+#[cfg(target_os = "linux")]
+pub fn default_handler(scheme: &str) -> Option<String> {
+    let output = std::process::Command::new("xdg-mime")
+        .args(["query", "default", &format!("x-scheme-handler/{scheme}")])
+        .output()
+        .ok()?;
+    let handler = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    (output.status.success() && !handler.is_empty()).then_some(handler)
+}
+
+#[cfg(target_os = "linux")]
+pub fn register() -> Result<()> {
+    unregister()?;
+
+    let exe_path = get_current_exe_path();
+    let exe_path = std::fs::canonicalize(&exe_path).unwrap_or(exe_path);
+    log!("Current exe path: {}", exe_path.display());
+
+    let desktop_file = desktop_file_path()?;
+    if let Some(folder) = desktop_file.parent() {
+        std::fs::create_dir_all(folder)?;
+    }
+    std::fs::write(&desktop_file, desktop_entry(&exe_path.to_string_lossy()))?;
+
+    log!("FirefoxRouter registered as a browser at {}", desktop_file.display());
+    log!("To set it as default, run: xdg-settings set default-web-browser {DESKTOP_FILE_NAME}");
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub fn unregister() -> Result<()> {
+    let desktop_file = desktop_file_path()?;
+    match std::fs::remove_file(&desktop_file) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+
+    log!("FirefoxRouter unregistered");
+    Ok(())
+}
+
+/// Desktop entries go in `$XDG_DATA_HOME/applications`, which defaults to `~/.local/share/applications`.
+#[cfg(target_os = "linux")]
+fn desktop_file_path() -> Result<std::path::PathBuf> {
+    use color_eyre::eyre::eyre;
+    use std::path::PathBuf;
+
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .filter(|it| !it.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|it| PathBuf::from(it).join(".local").join("share")))
+        .ok_or_else(|| eyre!("Couldn't find the user data folder, neither XDG_DATA_HOME nor HOME are set"))?;
+    Ok(data_home.join("applications").join(DESKTOP_FILE_NAME))
+}
+
+#[cfg(target_os = "linux")]
+fn desktop_entry(exe_path: &str) -> String {
+    // Quoted arguments of the Exec key must have '"', '`', '$' and '\' escaped with a backslash
+    let mut escaped_path = String::with_capacity(exe_path.len());
+    for c in exe_path.chars() {
+        if matches!(c, '"' | '`' | '$' | '\\') {
+            escaped_path.push('\\');
+        }
+        escaped_path.push(c);
+    }
+    format!(r#"[Desktop Entry]
+Type=Application
+Name=Firefox Router
+Comment=Routes URLs to Firefox using the active profile
+Exec="{escaped_path}" %u
+Icon=firefox
+Terminal=false
+Categories=Network;WebBrowser;
+MimeType=x-scheme-handler/http;x-scheme-handler/https;text/html;application/pdf;
+"#)
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn is_registered_application() -> bool {
+    false
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn has_start_menu_client() -> bool {
+    false
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn default_handler(_scheme: &str) -> Option<String> {
+    None
+}
+
+#[cfg(not(windows))]
+pub fn register_machine() -> Result<()> {
+    Err(color_eyre::eyre::eyre!("Machine-wide registration is only supported on Windows, use --register instead"))
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn register() -> Result<()> {
+    Err(color_eyre::eyre::eyre!("Registering as a browser is not supported on this platform"))
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn unregister() -> Result<()> {
+    Err(color_eyre::eyre::eyre!("Registering as a browser is not supported on this platform"))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn desktop_entry_handles_urls() {
+        let entry = desktop_entry("/opt/FirefoxRouter/FirefoxRouter");
+        assert!(entry.starts_with("[Desktop Entry]\n"), "Entry should start with the group header: {entry}");
+        assert!(entry.contains("Exec=\"/opt/FirefoxRouter/FirefoxRouter\" %u\n"), "Entry should pass the URL: {entry}");
+        assert!(entry.contains("MimeType=x-scheme-handler/http;x-scheme-handler/https;text/html;application/pdf;\n"));
+    }
+
+    #[test]
+    fn desktop_entry_escapes_exec_path() {
+        let entry = desktop_entry(r#"/home/me/my "apps"/$bin"#);
+        assert!(entry.contains(r#"Exec="/home/me/my \"apps\"/\$bin" %u"#), "Exec path should be escaped: {entry}");
+    }
+}
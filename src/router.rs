@@ -0,0 +1,3963 @@
+use crate::config::{config_dir_path, config_path, config_relative_path, glob_cache_path, import_profile_rules, init_config, init_config_path, read_app_config, watch_config, AppConfig, BrowserRule, Container, ContainerMode, OpenMode, ProfileRegexRule, ProfileRule, ProfileStrategy, RuleAction, SchemeAction, SchemeRule, DEFAULT_SPAWN_RETRIES};
+use crate::glob::Glob;
+use crate::history::{HistoryEntry, HistoryOutcome};
+use crate::profile_usage::{profile_usage_path, read_profile_usage, record_profile_use, ProfileUsage};
+use crate::profiles::{find_default_profile, list_profiles, locked_profile, FirefoxProfile};
+use crate::registration::{default_handler, has_start_menu_client, is_registered_application, register, register_machine, unregister, URL_HANDLER_ID};
+use crate::util::{load_env_file, percent_encode, percent_encode_path};
+use crate::{history, log_file, win};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::cell::LazyCell;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+use sysinfo::{Pid, Process, ProcessRefreshKind, ProcessesToUpdate, System, UpdateKind};
+
+const URL_PLACEHOLDER: &str = "{url}";
+/// The command (and arguments before the URL) opening a URL in the system's default browser.
+#[cfg(windows)]
+const DEFAULT_BROWSER_OPENER: [&str; 2] = ["rundll32.exe", "url.dll,FileProtocolHandler"];
+#[cfg(target_os = "macos")]
+const DEFAULT_BROWSER_OPENER: [&str; 1] = ["open"];
+#[cfg(not(any(windows, target_os = "macos")))]
+const DEFAULT_BROWSER_OPENER: [&str; 1] = ["xdg-open"];
+const DEFAULT_FIREFOX_EXECUTABLES: [&str; 5] = ["firefox.exe", "firefox-esr.exe", "firefox", "firefox-bin", "firefox-esr"];
+
+/// A running Firefox process, as returned by a [`ProcessSource`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FirefoxInfo {
+    path: String,
+    profile: Option<ProfileRef>,
+    /// When the process started, in seconds since the epoch, 0 for the Firefox about to be launched.
+    start_time: u64,
+}
+
+impl FirefoxInfo {
+    /// A Firefox started from the executable at `path` with `profile` (a name, or a directory when it
+    /// looks like a path), or without one.
+    pub fn new(path: &str, profile: Option<&str>, start_time: u64) -> Self {
+        Self { path: path.to_owned(), profile: profile.map(ProfileRef::from_arg), start_time }
+    }
+}
+
+/// Where a URL should be opened, as resolved from the profile rules.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Route {
+    /// The profile to open the URL in, the active one being used when neither this nor
+    /// `profile_pool` is set.
+    pub profile_name: Option<String>,
+    /// Set instead of `profile_name` by rules spreading their URLs across several profiles.
+    pub profile_pool: Option<ProfilePool>,
+    pub action: RuleAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfilePool {
+    pub profiles: Vec<String>,
+    pub strategy: ProfileStrategy,
+}
+
+/// What is done with a URL according to the config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// Filtered out by the ignore rules or the allowlist, so it's not opened at all.
+    Ignore,
+    /// Opened in Firefox, `url` being the URL after the rewrite rules and query stripping.
+    OpenInFirefox { url: String, route: Route },
+    /// Handed to another browser by a browser rule (or to the mail client for `mailto:` links), as
+    /// `command` with `args`.
+    OpenInBrowser { url: String, command: String, args: Vec<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum ProfileRef {
+    Named(String),
+    Path(PathBuf),
+}
+
+impl ProfileRef {
+    /// `-P` expects a profile name and `-profile` a profile directory, but since both end up being
+    /// used with either form, anything that looks like a path is treated as one.
+    fn from_arg(value: &str) -> Self {
+        if value.contains(['/', '\\']) || Path::new(value).exists() {
+            ProfileRef::Path(PathBuf::from(value))
+        } else {
+            ProfileRef::Named(value.to_owned())
+        }
+    }
+}
+
+impl std::fmt::Display for ProfileRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProfileRef::Named(name) => write!(f, "{name}"),
+            ProfileRef::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl PartialOrd for FirefoxInfo {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FirefoxInfo {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        let profile_cmp = match (&self.profile, &other.profile) {
+            (Some(self_profile), Some(other_profile)) => self_profile.cmp(other_profile),
+            (a, b) => b.cmp(a),
+        };
+        // The most recently started Firefox is the likeliest to be the one the user just interacted with
+        profile_cmp
+            .then_with(|| other.start_time.cmp(&self.start_time))
+            .then_with(|| self.path.cmp(&other.path))
+    }
+}
+
+/// Runs the app with the arguments it was launched with.
+pub fn run() -> Result<()> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    // Its output is a single token read by scripts, which the debug messages mustn't get mixed into
+    if args.first().is_some_and(|it| it == "--profile-for") {
+        crate::log_macro::print_diagnostics_to_stderr();
+    }
+    load_env_file();
+    log_file::enable_from_env();
+    let dry_run = take_flag(&mut args, "--dry-run") || is_link_opening_disabled();
+    let launcher: &dyn Launcher = if dry_run { &DryRunLauncher } else { &ProcessLauncher };
+
+    let result = match args.first().map(|s| s.as_str()) {
+        Some("--register") => register(),
+        Some("--register-machine") => register_machine(),
+        Some("--unregister") => unregister(),
+        Some("--reset") => {
+            win::attach_parent_console();
+            reset(take_flag(&mut args, "--config"))
+        },
+        Some("--watch") => watch(),
+        Some("--init-config") => {
+            win::attach_parent_console();
+            init_config_file()
+        },
+        Some("--check-config") => {
+            win::attach_parent_console();
+            check_config()
+        },
+        Some("--open-in") => open_in_profile(&args[1..], launcher),
+        Some("--test-url") => {
+            win::attach_parent_console();
+            test_urls(&args[1..])
+        },
+        Some("--history") => {
+            win::attach_parent_console();
+            print_history(&args[1..])
+        },
+        Some("--profile-for") => {
+            win::attach_parent_console();
+            print_profile_for(&args[1..])
+        },
+        Some("--import") => {
+            win::attach_parent_console();
+            import_rules(&args[1..])
+        },
+        Some("--status") => {
+            win::attach_parent_console();
+            print_status(take_flag(&mut args, "--json"))
+        },
+        Some("--help" | "-h") => {
+            win::attach_parent_console();
+            print_help();
+            Ok(())
+        },
+        Some("--version" | "-V") => {
+            win::attach_parent_console();
+            log!("FirefoxRouter {}", env!("CARGO_PKG_VERSION"));
+            Ok(())
+        },
+        _ => handle_links(args, launcher)
+    };
+    if let Err(e) = &result {
+        log_file::write(&format!("Error: {e:#}"));
+    }
+    result
+}
+
+fn print_help() {
+    log!("FirefoxRouter {}", env!("CARGO_PKG_VERSION"));
+    log!("Routes URLs to Firefox using the profile that is currently active.");
+    log!("");
+    log!("Usage: FirefoxRouter [--dry-run] <URL>...");
+    log!("       FirefoxRouter <COMMAND>");
+    log!("");
+    log!("Opens each URL in Firefox, after applying the filtering, rewriting and routing rules from the config file.");
+    log!("");
+    log!("Commands:");
+    log!("  --register          Register FirefoxRouter as a browser, so it can be set as the default one");
+    log!("  --register-machine  Register FirefoxRouter for all users of the machine (Windows only, requires elevation)");
+    log!("  --unregister        Remove the browser registration (both the per-user and the machine-wide one)");
+    log!("  --reset [--config]  Unregister and remove the cache, usage and log files (and the config file with --config)");
+    log!("  --watch             Stay running and report every time the config file is reloaded");
+    log!("  --status [--json]   Report whether FirefoxRouter is registered and set as the default browser");
+    log!("  --init-config       Write an example config file, unless there's one already");
+    log!("  --check-config      Validate the config file and report how many rules it has");
+    log!("  --test-url          Explain which rules match the given URLs, without opening them");
+    log!("  --open-in <PROFILE> Open the given URLs in PROFILE, regardless of the profile rules and the running Firefox");
+    log!("  --history [COUNT]   Print the last routed URLs (20 by default) recorded in the history_file of the config");
+    log!("  --profile-for <URL> Print only the profile URL opens in, <default> for the active one or <ignored>");
+    log!("  --import <FILE>     Print the profile rules of a file of 'domain<TAB>profile' lines, as a JSON config");
+    log!("  --help, -h          Print this help");
+    log!("  --version, -V       Print the version");
+    log!("");
+    log!("Options:");
+    log!("  --dry-run           Print the commands that would be run instead of opening the URLs");
+    log!("");
+    log!("Config file: {}", config_path().display());
+}
+
+/// Stays resident and reloads the config every time it changes, reporting whether it's still valid.
+fn watch() -> Result<()> {
+    let path = config_path();
+    let watcher = watch_config(&path)?;
+    log!("Watching {} for changes, config currently {}", path.display(), describe_config(watcher.config()));
+
+    for config in watcher {
+        log!("Config reloaded, {}", describe_config(config.as_ref()));
+    }
+    Ok(())
+}
+
+/// Prints the profile rules of the `domain<TAB>profile` file in `args` as a JSON config.
+fn import_rules(args: &[String]) -> Result<()> {
+    let [path] = args else {
+        return Err(eyre!("Expected a single file, usage: FirefoxRouter --import <FILE>"));
+    };
+    let contents = std::fs::read_to_string(path).map_err(|e| eyre!("Couldn't read '{path}': {e}"))?;
+    log!("{}", import_profile_rules(&contents)?);
+    Ok(())
+}
+
+/// Writes the example config to the per-user config folder (see [`init_config_path`]), leaving any
+/// existing one alone.
+fn init_config_file() -> Result<()> {
+    let path = init_config_path();
+    init_config(&path)?;
+    log!("Example config written to {}", path.display());
+    Ok(())
+}
+
+/// Validates the config, failing (and thus exiting with a non-zero code) if it doesn't parse, and
+/// warns about the rules that look like mistakes.
+fn check_config() -> Result<()> {
+    let path = config_path();
+    let dir = config_dir_path(&path);
+    match read_app_config()? {
+        Some(config) => {
+            log!("Config {} (and {}) is valid, {}", path.display(), dir.display(), describe_config(Some(&config)));
+            for warning in lint_config(&config) {
+                log!("Warning: {warning}");
+            }
+        },
+        None => {
+            log!("Config {} (and {}) is empty or missing, nothing to check", path.display(), dir.display());
+        },
+    }
+    Ok(())
+}
+
+/// Unregisters FirefoxRouter and removes the files it created: the glob cache, the profile usage, the
+/// history and the log files, along with the config file when `remove_config` is set. Files that don't exist are
+/// skipped, so it can be run any number of times.
+fn reset(remove_config: bool) -> Result<()> {
+    unregister()?;
+
+    let path = config_path();
+    // A broken config shouldn't prevent cleaning up, its log files are just left alone
+    let config = read_app_config().ok().flatten();
+    let log_paths = log_file::disable().into_iter()
+        .chain(config.as_ref().and_then(|it| it.logging.as_ref()).map(|it| config_relative_path(&it.path)))
+        .flat_map(|it| log_file::log_file_paths(&it));
+    let history_path = config.as_ref().and_then(|it| it.history_file.as_deref()).map(config_relative_path);
+
+    let mut files = vec![glob_cache_path(&path), profile_usage_path(&path)];
+    files.extend(log_paths);
+    files.extend(history_path);
+    if remove_config {
+        files.push(path);
+    }
+    let removed = remove_files(&files)?;
+    if removed.is_empty() {
+        log!("No file to remove");
+    }
+    for file in removed {
+        log!("Removed {}", file.display());
+    }
+    Ok(())
+}
+
+/// Removes the existing files among `files`, returning them.
+fn remove_files(files: &[PathBuf]) -> Result<Vec<&PathBuf>> {
+    let mut removed = Vec::new();
+    for file in files {
+        match std::fs::remove_file(file) {
+            Ok(()) => removed.push(file),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+            Err(e) => return Err(eyre!("Couldn't remove {}: {e}", file.display())),
+        }
+    }
+    Ok(removed)
+}
+
+/// How a URL goes through the filtering, rewriting and routing rules. Rules are referred to by
+/// their index in the config, and the rules after the filtering are only checked if the URL passed it.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct MatchExplanation {
+    /// Whether the URL skips the rules due to its scheme, see [`is_passthrough`].
+    is_passthrough: bool,
+    ignored_by_glob: Option<usize>,
+    ignored_by_regex: Option<String>,
+    /// Whether the URL passed the allowlist, which is always the case when it's empty.
+    is_allowed: bool,
+    allowed_by_glob: Option<usize>,
+    /// The URL after the rewrite rules and query stripping, if it passed the filtering.
+    final_url: Option<String>,
+    /// The rule opening the URL even though it was filtered out, thanks to a higher priority.
+    overriding_rule: Option<MatchedRule>,
+    browser_rule: Option<usize>,
+    profile_rule: Option<usize>,
+    profile_regex_rule: Option<usize>,
+    /// The scheme rule deciding what happens to the URL, when no browser or profile rule matched it.
+    scheme_rule: Option<(usize, SchemeAction)>,
+    /// Whether the URL opens in Firefox despite a `default_browser` scheme rule, as the default
+    /// browser is FirefoxRouter itself, see [`scheme_action`].
+    is_routed_back: bool,
+    route: Route,
+    /// The rule of each glob that matched (e.g. `profile_rules[1]`), along with the glob as written
+    /// and the regex it was translated to.
+    glob_regexes: Vec<(String, String, String)>,
+}
+
+impl MatchExplanation {
+    fn is_filtered_out(&self) -> bool {
+        let is_filtered = self.ignored_by_glob.is_some() || self.ignored_by_regex.is_some() || !self.is_allowed;
+        is_filtered && self.overriding_rule.is_none()
+    }
+
+    /// The rule deciding what happens to the URL, if any: the one filtering it out, or else the one
+    /// routing it.
+    fn deciding_rule(&self) -> Option<String> {
+        if self.is_filtered_out() {
+            return self.ignored_by_glob.map(|index| format!("ignored_urls[{index}]"))
+                .or(self.ignored_by_regex.as_ref().map(|_| "ignored_urls_regex".to_owned()))
+                .or(Some("allowed_urls".to_owned()));
+        }
+        self.browser_rule.map(MatchedRule::Browser)
+            .or(self.profile_rule.map(MatchedRule::Profile))
+            .or(self.profile_regex_rule.map(MatchedRule::ProfileRegex))
+            .map(|it| it.to_string())
+            .or(self.scheme_rule.as_ref().map(|(index, _)| format!("scheme_rules[{index}]")))
+    }
+}
+
+impl std::fmt::Display for MatchExplanation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_passthrough {
+            return writeln!(f, "  Result: opened unchanged, its scheme isn't routed");
+        }
+        for (rule, source, regex) in &self.glob_regexes {
+            writeln!(f, "  Pattern of {rule}: {source}")?;
+            writeln!(f, "  Regex of {rule}: {regex}")?;
+        }
+        if let Some(index) = self.ignored_by_glob {
+            writeln!(f, "  Ignored by ignored_urls[{index}]")?;
+        }
+        if let Some(regex) = &self.ignored_by_regex {
+            writeln!(f, "  Ignored by ignored_urls_regex '{regex}'")?;
+        }
+        match self.allowed_by_glob {
+            Some(index) => writeln!(f, "  Allowed by allowed_urls[{index}]")?,
+            None if !self.is_allowed => writeln!(f, "  Not allowed by any allowed_urls")?,
+            None => {},
+        }
+        if let Some(rule) = self.overriding_rule {
+            writeln!(f, "  Filtering overridden by {rule}, which has a higher priority")?;
+        }
+        if self.is_filtered_out() {
+            return writeln!(f, "  Result: filtered out, not opened");
+        }
+        if let Some(url) = &self.final_url {
+            writeln!(f, "  Opened as: {url}")?;
+        }
+        if let Some(index) = self.browser_rule {
+            return writeln!(f, "  Result: opened in another browser due to browser_rules[{index}]");
+        }
+        match &self.scheme_rule {
+            Some((index, SchemeAction::Ignore)) => return writeln!(f, "  Result: not opened due to scheme_rules[{index}]"),
+            Some((index, SchemeAction::DefaultBrowser)) if !self.is_routed_back => {
+                return writeln!(f, "  Result: opened in the system's default browser due to scheme_rules[{index}]");
+            }
+            Some((index, SchemeAction::DefaultBrowser)) => {
+                writeln!(f, "  Not handed to the system's default browser by scheme_rules[{index}], as it's FirefoxRouter itself")?;
+            }
+            _ => {}
+        }
+        let profile = match (&self.route.profile_name, &self.route.profile_pool) {
+            (Some(name), _) => format!("profile '{name}'"),
+            (None, Some(pool)) => format!("one of the profiles '{}'", pool.profiles.join("', '")),
+            (None, None) => "the active profile".to_owned(),
+        };
+        let action = match &self.route.action {
+            RuleAction::Open => String::new(),
+            RuleAction::PrivateWindow => " in a private window".to_owned(),
+            RuleAction::Container(Container { name, mode: ContainerMode::Reuse }) => format!(" in container '{name}'"),
+            RuleAction::Container(Container { name, mode: ContainerMode::Ephemeral }) => format!(" in a new container named after '{name}'"),
+            RuleAction::ReaderMode => " in reader mode".to_owned(),
+            RuleAction::TempProfile => " in a temporary profile".to_owned(),
+            RuleAction::Launch { extra_args, .. } => format!(" with the flags '{}'", extra_args.join(" ")),
+        };
+        let rule = self.profile_rule.map(MatchedRule::Profile)
+            .or(self.profile_regex_rule.map(MatchedRule::ProfileRegex))
+            .map(|it| it.to_string())
+            .or(self.scheme_rule.as_ref().map(|(index, _)| format!("scheme_rules[{index}]")));
+        match rule {
+            Some(rule) => writeln!(f, "  Result: opened in {profile}{action} due to {rule}"),
+            None => writeln!(f, "  Result: opened in {profile}{action}, no profile rule matched"),
+        }
+    }
+}
+
+/// Whether the router is registered as a browser and set as the default one.
+#[derive(Debug, PartialEq, Eq, serde::Serialize)]
+struct RegistrationStatus {
+    /// The `RegisteredApplications` entry on Windows, the desktop entry on Linux.
+    registered_application: bool,
+    /// The `StartMenuInternet` client key on Windows, the desktop entry on Linux.
+    start_menu_client: bool,
+    http_handler: Option<String>,
+    https_handler: Option<String>,
+    is_default: bool,
+}
+
+impl RegistrationStatus {
+    fn new(registered_application: bool, start_menu_client: bool, http_handler: Option<String>, https_handler: Option<String>) -> Self {
+        let is_default = [&http_handler, &https_handler].iter().all(|it| it.as_deref() == Some(URL_HANDLER_ID));
+        Self { registered_application, start_menu_client, http_handler, https_handler, is_default }
+    }
+}
+
+impl std::fmt::Display for RegistrationStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let presence = |present: bool| if present { "present" } else { "absent" };
+        writeln!(f, "Registered application: {}", presence(self.registered_application))?;
+        writeln!(f, "Browser client: {}", presence(self.start_menu_client))?;
+        writeln!(f, "Default http handler: {}", self.http_handler.as_deref().unwrap_or("none"))?;
+        writeln!(f, "Default https handler: {}", self.https_handler.as_deref().unwrap_or("none"))?;
+        if self.is_default {
+            writeln!(f, "FirefoxRouter is the default browser")
+        } else {
+            writeln!(f, "FirefoxRouter is not the default browser")
+        }
+    }
+}
+
+fn registration_status() -> RegistrationStatus {
+    RegistrationStatus::new(
+        is_registered_application(),
+        has_start_menu_client(),
+        default_handler("http"),
+        default_handler("https"),
+    )
+}
+
+/// Prints the registration status, as JSON when `json` is set.
+fn print_status(json: bool) -> Result<()> {
+    let status = registration_status();
+    if json {
+        log!("{}", serde_json::to_string_pretty(&status)?);
+    } else {
+        log!("{}", status.to_string().trim_end());
+    }
+    Ok(())
+}
+
+fn explain_match(url: &str, config: &AppConfig) -> MatchExplanation {
+    if is_passthrough(url, Some(config)) {
+        return MatchExplanation {
+            is_passthrough: true,
+            is_allowed: true,
+            final_url: Some(url.to_owned()),
+            ..Default::default()
+        };
+    }
+    let ignored_by_glob = matches_any(&config.ignored_urls, url);
+    let ignored_by_regex = config.ignored_urls_regex.iter()
+        .find(|it| it.as_ref().is_match(url))
+        .map(|it| it.as_ref().as_str().to_owned());
+    let allowed_by_glob = matches_any(&config.allowed_urls, url);
+    let mut explanation = MatchExplanation {
+        ignored_by_glob,
+        ignored_by_regex,
+        is_allowed: config.allowed_urls.is_empty() || allowed_by_glob.is_some(),
+        allowed_by_glob,
+        ..Default::default()
+    };
+    if let Some(index) = ignored_by_glob {
+        explanation.glob_regexes.push(glob_match(format!("ignored_urls[{index}]"), &config.ignored_urls[index], url));
+    }
+    if let Some(index) = allowed_by_glob {
+        explanation.glob_regexes.push(glob_match(format!("allowed_urls[{index}]"), &config.allowed_urls[index], url));
+    }
+    let final_url = clean_query(&apply_rewrites(url, Some(config)), &config.strip_query_params);
+    let matched_rule = matching_rule(&final_url, config);
+    if explanation.is_filtered_out() {
+        explanation.overriding_rule = matched_rule
+            .filter(|(_, priority)| *priority > FILTER_PRIORITY)
+            .map(|(rule, _)| rule);
+        if explanation.is_filtered_out() {
+            return explanation;
+        }
+    }
+
+    match matched_rule.map(|(rule, _)| rule) {
+        Some(rule @ MatchedRule::Browser(index)) => {
+            explanation.browser_rule = Some(index);
+            explanation.glob_regexes.push(glob_match(rule.to_string(), &config.browser_rules[index].pattern, &final_url));
+        }
+        Some(rule @ MatchedRule::Profile(index)) => {
+            explanation.profile_rule = Some(index);
+            explanation.route = profile_rule_route(&config.profile_rules[index]);
+            explanation.glob_regexes.push(glob_match(rule.to_string(), &config.profile_rules[index].pattern, &final_url));
+        }
+        Some(MatchedRule::ProfileRegex(index)) => {
+            explanation.profile_regex_rule = Some(index);
+            explanation.route = profile_regex_rule_route(&config.profile_rules_regex[index]);
+        }
+        None => if let Some(index) = matching_scheme_rule(&final_url, config) {
+            let rule = &config.scheme_rules[index];
+            explanation.scheme_rule = Some((index, rule.action.clone()));
+            explanation.is_routed_back = scheme_action(&final_url, Some(&rule.action), is_default_browser).is_none();
+            explanation.route = scheme_rule_route(rule);
+        }
+    }
+    explanation.final_url = Some(final_url);
+    explanation
+}
+
+/// The `rule` whose `glob` matched `url`, with the glob's source and the regex of it that applies
+/// to `url`, depending on whether it has a protocol.
+fn glob_match(rule: String, glob: &Glob, url: &str) -> (String, String, String) {
+    let (with_protocol, without_protocol) = glob.regex_source();
+    let regex = match url.contains("://") {
+        true => with_protocol,
+        false => without_protocol,
+    };
+    (rule, glob.source().to_owned(), regex.to_owned())
+}
+
+/// Decides what to do with `url`, the same way the app does when asked to open it. Local file paths
+/// and bare hosts are handled as their `file://` and `https://` URLs, see [`normalize_input`].
+pub fn resolve(url: &str, config: &AppConfig) -> Decision {
+    let explanation = explain_match(&normalize_input(url), config);
+    // Only URLs passing the filtering get a final URL
+    let Some(url) = explanation.final_url else {
+        return Decision::Ignore;
+    };
+    if explanation.is_passthrough && is_mailto(&url) {
+        return default_opener_decision(url);
+    }
+    match explanation.scheme_rule.map(|(_, action)| action) {
+        Some(SchemeAction::Ignore) => return Decision::Ignore,
+        Some(SchemeAction::DefaultBrowser) if !explanation.is_routed_back => return default_opener_decision(url),
+        _ => {}
+    }
+    match explanation.browser_rule.map(|index| &config.browser_rules[index]) {
+        Some(rule) => Decision::OpenInBrowser {
+            args: browser_args(&url, rule),
+            command: rule.command.clone(),
+            url,
+        },
+        None => Decision::OpenInFirefox { url, route: explanation.route },
+    }
+}
+
+/// Hands `url` to the app the system associates with its scheme.
+fn default_opener_decision(url: String) -> Decision {
+    let [command, args @ ..] = DEFAULT_BROWSER_OPENER;
+    Decision::OpenInBrowser {
+        command: command.to_owned(),
+        args: args.iter().map(|it| it.to_string()).chain([url.clone()]).collect(),
+        url,
+    }
+}
+
+/// Prints how each URL would be handled by the config, without opening anything.
+fn test_urls(urls: &[String]) -> Result<()> {
+    if urls.is_empty() {
+        return Err(eyre!("No URL given, usage: FirefoxRouter --test-url <URL>..."));
+    }
+    let path = config_path();
+    let Some(config) = read_app_config()? else {
+        log!("Config {} is empty or missing, URLs will be opened as they are in the active profile", path.display());
+        return Ok(());
+    };
+    for url in urls {
+        let url = normalize_input(url);
+        log!("{url}");
+        log!("{}", explain_match(&url, &config).to_string().trim_end());
+    }
+    Ok(())
+}
+
+/// How many entries `--history` prints when not given a count.
+const DEFAULT_HISTORY_COUNT: usize = 20;
+
+/// Prints the last entries of the history file, oldest first.
+fn print_history(args: &[String]) -> Result<()> {
+    let count = match args {
+        [] => DEFAULT_HISTORY_COUNT,
+        [count] => count.parse().map_err(|_| eyre!("Invalid count '{count}', usage: FirefoxRouter --history [COUNT]"))?,
+        _ => return Err(eyre!("Too many arguments, usage: FirefoxRouter --history [COUNT]")),
+    };
+    let config = read_app_config()?;
+    let Some(path) = config.as_ref().and_then(|it| it.history_file.as_deref()).map(config_relative_path) else {
+        log!("No history_file set in {}, routed URLs aren't recorded", config_path().display());
+        return Ok(());
+    };
+    let entries = history::read_last(&path, count).map_err(|e| eyre!("Couldn't read the history {}: {e}", path.display()))?;
+    if entries.is_empty() {
+        log!("No URL recorded in {} yet", path.display());
+    }
+    for entry in entries {
+        log!("{entry}");
+    }
+    Ok(())
+}
+
+/// Prints the profile `url` opens in as a single token, for scripts.
+fn print_profile_for(args: &[String]) -> Result<()> {
+    let [url] = args else {
+        return Err(eyre!("Expected a single URL, usage: FirefoxRouter --profile-for <URL>"));
+    };
+    // Not logged, the log file being for diagnostics
+    println!("{}", profile_for(url, read_app_config()?.as_ref()));
+    Ok(())
+}
+
+/// The profile `url` opens in according to `config`, as a single token: the profile's name,
+/// `<default>` for the active (or default) profile, `<temp>` for a temporary one, `<browser>` when
+/// it's handed to another browser and `<ignored>` when it's not opened. Rules spreading their URLs
+/// across several profiles give all of them, separated by commas, since the running Firefox decides.
+pub fn profile_for(url: &str, config: Option<&AppConfig>) -> String {
+    let Some(config) = config else {
+        return "<default>".to_owned();
+    };
+    let route = match resolve(url, config) {
+        Decision::Ignore => return "<ignored>".to_owned(),
+        Decision::OpenInBrowser { .. } => return "<browser>".to_owned(),
+        Decision::OpenInFirefox { route, .. } => route,
+    };
+    match (route.action, route.profile_name, route.profile_pool) {
+        (RuleAction::TempProfile, _, _) => "<temp>".to_owned(),
+        (_, Some(name), _) => name,
+        (_, None, Some(pool)) => pool.profiles.join(","),
+        (_, None, None) => "<default>".to_owned(),
+    }
+}
+
+fn describe_config(config: Option<&AppConfig>) -> String {
+    let Some(config) = config else {
+        return "empty or missing".to_owned();
+    };
+    format!(
+        "has {} ignored URLs, {} ignored URL regexes, {} allowed URLs, {} rewrite rules, {} profile rules and {} browser rules",
+        config.ignored_urls.len(),
+        config.ignored_urls_regex.len(),
+        config.allowed_urls.len(),
+        config.rewrite_rules.len(),
+        config.profile_rules.len() + config.profile_rules_regex.len(),
+        config.browser_rules.len(),
+    )
+}
+
+/// A rule of the config that is likely a mistake, found by [`lint_config`].
+#[derive(Debug, PartialEq, Eq)]
+enum ConfigWarning {
+    /// A glob of `ignored_urls` or `allowed_urls` listed again later in the same list.
+    DuplicateGlob { list: &'static str, first: usize, duplicate: usize },
+    /// A rule that never decides where a URL opens, since `shadowed_by` matches all of its URLs and
+    /// wins over it, by priority or by coming first.
+    ShadowedRule { rule: MatchedRule, shadowed_by: MatchedRule },
+    /// A rule whose URLs are all ignored, its priority not being high enough to override that.
+    IgnoredRule { rule: MatchedRule, ignored_by: usize },
+}
+
+impl std::fmt::Display for ConfigWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigWarning::DuplicateGlob { list, first, duplicate } => {
+                write!(f, "{list}[{duplicate}] is the same as {list}[{first}]")
+            },
+            ConfigWarning::ShadowedRule { rule, shadowed_by } => {
+                write!(f, "{rule} never applies, {shadowed_by} matches all of its URLs and takes precedence")
+            },
+            ConfigWarning::IgnoredRule { rule, ignored_by } => {
+                write!(f, "{rule} never applies, all of its URLs are ignored by ignored_urls[{ignored_by}]")
+            },
+        }
+    }
+}
+
+/// Looks for rules that can't have any effect: duplicated globs, rules shadowed by broader ones and
+/// rules whose URLs are all ignored. Globs are compared through [`Glob::covers`], which only catches
+/// the obvious cases (the same glob, or a longer glob than one ending with `/**`), and the regex
+/// profile rules aren't checked.
+fn lint_config(config: &AppConfig) -> Vec<ConfigWarning> {
+    let mut warnings = Vec::new();
+    for (list, globs) in [("ignored_urls", &config.ignored_urls), ("allowed_urls", &config.allowed_urls)] {
+        for (duplicate, glob) in globs.iter().enumerate() {
+            if let Some(first) = globs[..duplicate].iter().position(|it| it.covers(glob) && glob.covers(it)) {
+                warnings.push(ConfigWarning::DuplicateGlob { list, first, duplicate });
+            }
+        }
+    }
+
+    // In the order ties are broken in
+    let rules: Vec<(MatchedRule, &Glob, i32)> = config.browser_rules.iter()
+        .enumerate()
+        .map(|(index, rule)| (MatchedRule::Browser(index), &rule.pattern, rule.priority))
+        .chain(config.profile_rules.iter()
+            .enumerate()
+            .map(|(index, rule)| (MatchedRule::Profile(index), &rule.pattern, rule.priority)))
+        .collect();
+    for (index, &(rule, glob, priority)) in rules.iter().enumerate() {
+        let shadowed_by = rules.iter()
+            .enumerate()
+            .filter(|&(other_index, &(_, other_glob, other_priority))| other_index != index
+                && (other_priority > priority || (other_priority == priority && other_index < index))
+                && other_glob.covers(glob))
+            .map(|(_, &(other_rule, _, _))| other_rule)
+            .next();
+        if let Some(shadowed_by) = shadowed_by {
+            warnings.push(ConfigWarning::ShadowedRule { rule, shadowed_by });
+            continue;
+        }
+        // A later negated glob may un-ignore some of the URLs
+        let ignored_by = config.ignored_urls.iter()
+            .enumerate()
+            .filter(|(ignore_index, _)| !config.ignored_urls[ignore_index + 1..].iter().any(Glob::is_negated))
+            .find(|(_, it)| it.covers(glob))
+            .map(|(ignore_index, _)| ignore_index);
+        if let Some(ignored_by) = ignored_by.filter(|_| priority <= FILTER_PRIORITY) {
+            warnings.push(ConfigWarning::IgnoredRule { rule, ignored_by });
+        }
+    }
+    warnings
+}
+
+/// Removes every occurrence of `flag` from `args`, returning whether it was present.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let len = args.len();
+    args.retain(|it| it != flag);
+    args.len() != len
+}
+
+/// Debug builds can also enable dry-run mode through the `DISABLE_LINK_OPENING` env variable.
+fn is_link_opening_disabled() -> bool {
+    cfg!(debug_assertions) && std::env::var("DISABLE_LINK_OPENING") == Ok("true".to_owned())
+}
+
+fn handle_links(args: Vec<String>, launcher: &dyn Launcher) -> Result<()> {
+    debug_log!("Args: {:?}", args);
+
+    let config = read_app_config()?;
+    if enable_log_file(config.as_ref()) {
+        debug_log!("Args: {:?}", args);
+    }
+    open_links(args, config.as_ref(), launcher, &SystemProcesses::new(config.as_ref()))
+}
+
+/// Opens `args` as configured by `config`, launching the browsers through `launcher` and taking the
+/// running Firefox from `processes`.
+pub fn open_links(args: Vec<String>, config: Option<&AppConfig>, launcher: &dyn Launcher, processes: &dyn ProcessSource) -> Result<()> {
+    let mut history = History::new(config, launcher.is_dry_run());
+    let result = route_links(args, config, launcher, processes, &mut history);
+    // Even when a launch failed, so the history tells which URLs didn't open
+    history.write();
+    result
+}
+
+fn route_links(
+    args: Vec<String>,
+    config: Option<&AppConfig>,
+    launcher: &dyn Launcher,
+    processes: &dyn ProcessSource,
+    history: &mut History,
+) -> Result<()> {
+    let dry_run = launcher.is_dry_run();
+    let args: Vec<String> = args.iter().map(|it| normalize_input(it)).collect();
+
+    for item in args.iter() {
+        info!("Requested URL open: {item}");
+    }
+
+    let (passthrough_urls, args): (Vec<String>, Vec<String>) = args.into_iter()
+        .partition(|it| is_passthrough(it, config));
+    let mut passthrough_firefox_urls = Vec::new();
+    for url in passthrough_urls {
+        if is_mailto(&url) {
+            debug_log!("Handing '{url}' to the system's mail client");
+            let result = launcher.launch(&mut default_opener_command(&url));
+            history.record_launch(&url, &result, HistoryOutcome::DefaultBrowser, None);
+            result?;
+        } else {
+            debug_log!("Opening '{url}' in Firefox unchanged, its scheme isn't routed");
+            passthrough_firefox_urls.push(url);
+        }
+    }
+
+    let filtered_args = filter_args(config, &args)?;
+    history.record_filtered(&args, &filtered_args);
+    let args = filtered_args;
+    if args.is_empty() && passthrough_firefox_urls.is_empty() {
+        debug_log!("All URLs got filtered out, nothing to do");
+        return Ok(());
+    }
+
+    let args = rewrite_urls(&args, config);
+
+    let mut firefox_urls = Vec::with_capacity(args.len());
+    for url in args {
+        if let Some(rule) = resolve_browser_rule(&url, config) {
+            debug_log!("Routing URL to '{}' due to configured browser rules", rule.command);
+            let result = open_with_browser(&url, rule, launcher);
+            history.record_launch(&url, &result, HistoryOutcome::Browser, None);
+            result?;
+            continue;
+        }
+        match scheme_action(&url, resolve_scheme_rule(&url, config).map(|it| &it.action), || launcher.is_default_browser()) {
+            Some(SchemeAction::Ignore) => {
+                debug_log!("Not opening '{url}' due to configured scheme rules");
+                history.record(&url, HistoryOutcome::Ignored, None);
+            }
+            Some(SchemeAction::DefaultBrowser) => {
+                debug_log!("Handing '{url}' to the system's default browser due to configured scheme rules");
+                let mut command = default_opener_command(&url);
+                command.env(FALLBACK_ENV_VAR, "1");
+                let result = launcher.launch(&mut command);
+                history.record_launch(&url, &result, HistoryOutcome::DefaultBrowser, None);
+                result?;
+            }
+            Some(SchemeAction::Profile(_)) | None => firefox_urls.push(url),
+        }
+    }
+    let args = firefox_urls;
+    if args.is_empty() && passthrough_firefox_urls.is_empty() {
+        debug_log!("All URLs got routed to other browsers, nothing left to open in Firefox");
+        return Ok(());
+    }
+
+    let use_active_profile = config.is_none_or(|it| it.use_active_profile);
+    let require_running = config.is_some_and(|it| it.require_running);
+    let (mut firefox_processes, mut window_firefox) = if use_active_profile || require_running {
+        find_running_firefox(processes)
+    } else {
+        debug_log!("Active profile detection disabled, not looking for running Firefox processes");
+        (Vec::new(), None)
+    };
+    if require_running && firefox_processes.is_empty() {
+        let urls: Vec<String> = args.iter().chain(&passthrough_firefox_urls).cloned().collect();
+        match default_browser_commands(&urls, launcher.is_default_browser()) {
+            Some(commands) => {
+                debug_log!("Firefox isn't running, handing {} URLs to the system's default browser", urls.len());
+                for (url, mut command) in urls.iter().zip(commands) {
+                    let result = launcher.launch(&mut command);
+                    history.record_launch(url, &result, HistoryOutcome::DefaultBrowser, None);
+                    result?;
+                }
+                return Ok(());
+            }
+            None => warn!("Firefox isn't running, but FirefoxRouter is the default browser, so opening the URLs in Firefox anyway"),
+        }
+    }
+    if !use_active_profile {
+        firefox_processes.clear();
+        window_firefox = None;
+    }
+
+    let mut routes = group_by_route(config, args);
+    if !passthrough_firefox_urls.is_empty() {
+        routes.push((Route::default(), passthrough_firefox_urls));
+    }
+    let is_prompt_needed = !dry_run
+        && config.is_some_and(|it| it.prompt_on_ambiguous)
+        && routes.iter().any(|(route, _)| route.profile_name.is_none() && route.profile_pool.is_none());
+    let chosen_firefox = if is_prompt_needed {
+        prompt_for_firefox(&firefox_processes, PROFILE_PROMPT_TIMEOUT)
+    } else {
+        None
+    };
+
+    let active_firefox = pick_active_firefox(chosen_firefox, window_firefox.as_ref(), &firefox_processes);
+    match active_firefox {
+        None => debug_log!("No Firefox processes found, links without a profile rule will open in the default profile"),
+        Some(info) if info.profile.is_some() => debug_log!("Found existing Firefox process with an active profile"),
+        Some(_) => debug_log!("Didn't spot any Firefox with profile currently in use, links without a profile rule will open in the default profile"),
+    }
+
+    let usage_path = profile_usage_path(&config_path());
+    let mut groups: Vec<LaunchGroup> = Vec::new();
+    for (route, urls) in routes {
+        let pool_profile = route.profile_pool.as_ref().map(|pool| {
+            let profile = choose_profile(pool, &firefox_processes, &read_profile_usage(&usage_path));
+            if !dry_run {
+                record_profile_use(&usage_path, &profile.to_string());
+            }
+            profile
+        });
+        let routed_profile = route.profile_name.map(ProfileRef::Named).or(pool_profile);
+        if let Some(profile) = &routed_profile {
+            debug_log!("Routing {} URLs to profile '{profile}' due to configured profile rules", urls.len());
+        }
+        let profile = match route.action {
+            // Gets its own profile on launch
+            RuleAction::TempProfile => None,
+            _ => resolve_profile(routed_profile, active_firefox, config),
+        };
+        add_to_group(&mut groups, profile, route.action, urls);
+    }
+    // Only a Firefox that isn't running yet can race with itself on startup
+    let startup_timeout = (use_active_profile && firefox_processes.is_empty() && !dry_run).then_some(FIREFOX_STARTUP_TIMEOUT);
+    let opened_profiles = launch_grouped(groups, active_firefox, startup_timeout, config, launcher, processes, history)?;
+
+    // Only after every launch, as showing the notification can take a while
+    if !dry_run && config.is_some_and(|it| it.notify_on_route)
+        && let Some(message) = route_notification(&opened_profiles, find_default_profile().as_ref()) {
+        win::show_notification("FirefoxRouter", &message);
+    }
+    Ok(())
+}
+
+/// What was done with each URL of an `open_links` call, recorded as it's decided and appended to
+/// the history file of the config at the end. Nothing is recorded without a history file or on a
+/// dry run.
+struct History<'a> {
+    config: Option<&'a AppConfig>,
+    entries: Vec<HistoryEntry>,
+}
+
+impl<'a> History<'a> {
+    fn new(config: Option<&'a AppConfig>, dry_run: bool) -> Self {
+        let config = config.filter(|it| it.history_file.is_some() && !dry_run);
+        Self { config, entries: Vec::new() }
+    }
+
+    /// Records what was done with `url`, which made it past the filtering, `profile` being the
+    /// Firefox profile it was launched in.
+    fn record(&mut self, url: &str, outcome: HistoryOutcome, profile: Option<&ProfileRef>) {
+        let Some(config) = self.config else {
+            return;
+        };
+        let profile = profile.map(ProfileRef::to_string);
+        self.entries.push(HistoryEntry::new(url.to_owned(), routing_rule(url, config), profile, outcome));
+    }
+
+    /// Records `url` as `outcome` if it was launched, or as failed.
+    fn record_launch(&mut self, url: &str, result: &std::io::Result<()>, outcome: HistoryOutcome, profile: Option<&ProfileRef>) {
+        match result {
+            Ok(()) => self.record(url, outcome, profile),
+            Err(_) => self.record(url, HistoryOutcome::Failed, None),
+        }
+    }
+
+    /// Records the URLs of `urls` the filtering removed, as ignored by the rule that removed them.
+    /// The duplicates are left out, as they were opened once.
+    fn record_filtered(&mut self, urls: &[String], kept: &[String]) {
+        let Some(config) = self.config else {
+            return;
+        };
+        let kept: HashSet<&str> = kept.iter().map(String::as_str).collect();
+        for url in urls.iter().filter(|it| !kept.contains(it.as_str())) {
+            self.entries.push(HistoryEntry::new(url.clone(), filtering_rule(url, config), None, HistoryOutcome::Ignored));
+        }
+    }
+
+    /// Appends the recorded entries to the history file. Failing to do so doesn't prevent opening the
+    /// URLs, so errors are just logged.
+    fn write(self) {
+        let Some(path) = self.config.and_then(|it| it.history_file.as_deref()) else {
+            return;
+        };
+        if self.entries.is_empty() {
+            return;
+        }
+        let path = config_relative_path(path);
+        if let Err(e) = history::append(&path, &self.entries) {
+            debug_log!("Couldn't write the history to {}: {e}", path.display());
+        }
+    }
+}
+
+/// The rule routing `url`, once rewritten, if any.
+fn routing_rule(url: &str, config: &AppConfig) -> Option<String> {
+    if is_passthrough(url, Some(config)) {
+        return None;
+    }
+    matching_rule(url, config).map(|(rule, _)| rule.to_string())
+        .or_else(|| matching_scheme_rule(url, config).map(|index| format!("scheme_rules[{index}]")))
+}
+
+/// The rule filtering `url` out, the length bounds being checked first as in [`filter_args`].
+fn filtering_rule(url: &str, config: &AppConfig) -> Option<String> {
+    let len = url.chars().count();
+    if config.min_url_len.is_some_and(|it| len < it) {
+        return Some("min_url_len".to_owned());
+    }
+    if config.max_url_len.is_some_and(|it| len > it) {
+        return Some("max_url_len".to_owned());
+    }
+    explain_match(url, config).deciding_rule()
+}
+
+/// Enables the log file of the config, unless logging to a file is enabled already. Returns whether
+/// it was enabled.
+fn enable_log_file(config: Option<&AppConfig>) -> bool {
+    let Some(logging) = config.and_then(|it| it.logging.as_ref()).filter(|it| it.enabled && !log_file::is_enabled()) else {
+        return false;
+    };
+    log_file::enable(config_relative_path(&logging.path));
+    true
+}
+
+/// Runs the rewrite rules and the query stripping over `urls`, giving the URLs to open.
+fn rewrite_urls(urls: &[String], config: Option<&AppConfig>) -> Vec<String> {
+    let strip_query_params = config.map(|it| it.strip_query_params.as_slice()).unwrap_or_default();
+    urls.iter()
+        .map(|url| apply_rewrites(url, config))
+        .map(|url| clean_query(&url, strip_query_params))
+        .collect()
+}
+
+/// Opens the URLs following the profile in `args` in that profile, regardless of the profile rules
+/// and of the running Firefox, for scripts. The URLs still go through the filtering and rewriting
+/// rules, except for the ones whose scheme is passed through.
+fn open_in_profile(args: &[String], launcher: &dyn Launcher) -> Result<()> {
+    let [profile, urls @ ..] = args else {
+        return Err(eyre!("No profile given, usage: FirefoxRouter --open-in <PROFILE> <URL>..."));
+    };
+    let config = read_app_config()?;
+    if enable_log_file(config.as_ref()) {
+        debug_log!("Args: {:?}", args);
+    }
+    open_urls_in_profile(profile, urls, config.as_ref(), launcher)
+}
+
+fn open_urls_in_profile(profile: &str, urls: &[String], config: Option<&AppConfig>, launcher: &dyn Launcher) -> Result<()> {
+    let urls: Vec<String> = urls.iter().map(|it| normalize_input(it)).collect();
+    let (passthrough_urls, urls): (Vec<String>, Vec<String>) = urls.into_iter()
+        .partition(|it| is_passthrough(it, config));
+    let mut urls = rewrite_urls(&filter_args(config, &urls)?, config);
+    urls.extend(passthrough_urls);
+    if urls.is_empty() {
+        debug_log!("All URLs got filtered out, nothing to do");
+        return Ok(());
+    }
+
+    let firefox_path = resolve_firefox_path(config, None);
+    let firefox_info = FirefoxInfo {
+        path: firefox_path.to_string_lossy().into_owned(),
+        profile: Some(ProfileRef::from_arg(profile)),
+        start_time: 0,
+    };
+    let open_mode = config.map(|it| it.open_mode).unwrap_or_default();
+    if let Err(e) = open_with_firefox(&urls, &firefox_path, Some(&firefox_info), &RuleAction::Open, open_mode, spawn_retries(config), launcher) {
+        handle_firefox_error(e, &urls, &firefox_path, config, launcher)?;
+    }
+    Ok(())
+}
+
+/// Returns the running Firefox processes, sorted, along with the one owning the frontmost Firefox window.
+fn find_running_firefox(processes: &dyn ProcessSource) -> (Vec<FirefoxInfo>, Option<FirefoxInfo>) {
+    let mut firefox_processes = processes.firefox_processes();
+    firefox_processes.sort();
+
+    let window_firefox = processes.frontmost_firefox();
+    if window_firefox.is_some() {
+        debug_log!("Found the frontmost Firefox window, preferring its profile");
+    }
+    (firefox_processes, window_firefox)
+}
+
+/// The Firefox whose profile the links without a profile rule open in: the one the user picked, else
+/// the one of the frontmost window, else the first of `firefox_processes` (see [`FirefoxInfo`]'s order).
+fn pick_active_firefox<'a>(
+    chosen_firefox: Option<&'a FirefoxInfo>,
+    window_firefox: Option<&'a FirefoxInfo>,
+    firefox_processes: &'a [FirefoxInfo],
+) -> Option<&'a FirefoxInfo> {
+    chosen_firefox.or(window_firefox).or(firefox_processes.first())
+}
+
+/// Picks the profile of `pool` to open URLs in, according to its strategy. See [`ProfileStrategy`]
+/// for the tie-breaking.
+fn choose_profile(pool: &ProfilePool, running: &[FirefoxInfo], usage: &ProfileUsage) -> ProfileRef {
+    let running_count = |name: &str| running.iter()
+        .filter(|it| matches!(&it.profile, Some(ProfileRef::Named(profile)) if profile.eq_ignore_ascii_case(name)))
+        .count();
+    let chosen = pool.profiles.iter()
+        .enumerate()
+        .min_by_key(|(index, name)| {
+            let load = match pool.strategy {
+                ProfileStrategy::LeastLoaded => running_count(name),
+                ProfileStrategy::RoundRobin => 0,
+            };
+            (load, usage.get(name.as_str()).copied().unwrap_or_default(), *index)
+        })
+        .map(|(_, name)| name.clone())
+        .unwrap_or_default();
+    ProfileRef::Named(chosen)
+}
+
+/// How long the user has to pick a profile, before falling back to the active one so links opened
+/// by automated tools don't hang forever.
+const PROFILE_PROMPT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Lets the user pick one of the running Firefox profiles, when there's more than one. Returns `None`
+/// if the user didn't pick any before `timeout`.
+fn prompt_for_firefox(firefox_processes: &[FirefoxInfo], timeout: Duration) -> Option<&FirefoxInfo> {
+    let candidates = distinct_profiles(firefox_processes);
+    if candidates.len() < 2 {
+        return None;
+    }
+    debug_log!("Found {} running profiles, asking which one to use", candidates.len());
+    let options: Vec<String> = candidates.iter()
+        .filter_map(|it| it.profile.as_ref().map(ToString::to_string))
+        .collect();
+    let chosen = run_with_timeout(timeout, move || {
+        win::choose_option("FirefoxRouter", "Open the links in which Firefox profile?", &options)
+    }).flatten();
+    if chosen.is_none() {
+        debug_log!("No profile was picked, using the active one");
+    }
+    chosen.map(|index| candidates[index])
+}
+
+/// The first process of each running profile, so each profile is offered only once.
+fn distinct_profiles(firefox_processes: &[FirefoxInfo]) -> Vec<&FirefoxInfo> {
+    let mut candidates: Vec<&FirefoxInfo> = Vec::new();
+    for info in firefox_processes.iter().filter(|it| it.profile.is_some()) {
+        if !candidates.iter().any(|it| it.profile == info.profile) {
+            candidates.push(info);
+        }
+    }
+    candidates
+}
+
+/// Runs `f` on another thread, giving up on it after `timeout`. The thread is left running in that
+/// case, so `f` shouldn't hold anything the rest of the app needs.
+fn run_with_timeout<T: Send + 'static>(timeout: Duration, f: impl FnOnce() -> T + Send + 'static) -> Option<T> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = sender.send(f());
+    });
+    receiver.recv_timeout(timeout).ok()
+}
+
+/// The message telling in which profiles the URLs were opened, or `None` if they were all opened
+/// in the default profile, where the user would expect them anyway.
+fn route_notification(profiles: &[ProfileRef], default_profile: Option<&FirefoxProfile>) -> Option<String> {
+    let mut names: Vec<String> = Vec::new();
+    for profile in profiles {
+        let is_default = default_profile.is_some_and(|default| match profile {
+            ProfileRef::Named(name) => *name == default.name,
+            ProfileRef::Path(path) => *path == default.path,
+        });
+        let name = match profile {
+            ProfileRef::Path(path) => path.file_name().map_or_else(|| profile.to_string(), |it| it.to_string_lossy().into_owned()),
+            ProfileRef::Named(name) => name.clone(),
+        };
+        if !is_default && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    match names.as_slice() {
+        [] => None,
+        [name] => Some(format!("Opened in profile: {name}")),
+        names => Some(format!("Opened in profiles: {}", names.join(", "))),
+    }
+}
+
+/// Schemes going through the rules when `passthrough_schemes` isn't configured, the others being
+/// opened as they are since they have no host (`about:`, `mailto:`) or aren't meant to be routed.
+const ROUTED_SCHEMES: [&str; 3] = ["http", "https", "file"];
+
+/// Whether `url` is opened as it is instead of going through the rules, due to its scheme. URLs
+/// without a scheme always go through the rules.
+fn is_passthrough(url: &str, config: Option<&AppConfig>) -> bool {
+    let Some(scheme) = url_scheme(url) else {
+        return false;
+    };
+    if config.is_some_and(|it| it.scheme_rules.iter().any(|rule| rule.scheme.eq_ignore_ascii_case(scheme))) {
+        return false;
+    }
+    match config.and_then(|it| it.passthrough_schemes.as_ref()) {
+        Some(schemes) => schemes.iter().any(|it| it.eq_ignore_ascii_case(scheme)),
+        None => !ROUTED_SCHEMES.iter().any(|it| it.eq_ignore_ascii_case(scheme)),
+    }
+}
+
+/// `mailto:` links are opened by the system's mail client rather than Firefox.
+fn is_mailto(url: &str) -> bool {
+    url_scheme(url).is_some_and(|it| it.eq_ignore_ascii_case("mailto"))
+}
+
+/// The scheme of `url` (without the `:`), if it starts with one.
+fn url_scheme(url: &str) -> Option<&str> {
+    let (scheme, _) = url.split_once(':')?;
+    let mut chars = scheme.chars();
+    let is_valid = chars.next().is_some_and(|it| it.is_ascii_alphabetic())
+        && chars.all(|it| it.is_ascii_alphanumeric() || matches!(it, '+' | '-' | '.'));
+    is_valid.then_some(scheme)
+}
+
+/// Turns what the router was handed into the URL it's filtered, routed and opened as: local paths
+/// become `file://` URLs (see [`file_path_to_url`]) and bare hosts such as `www.example.com`, which
+/// can come from "open with" or scripts, get an `https://` scheme, so they match the same patterns
+/// as the URL Firefox would load for them. URLs with any other scheme (`about:`, `mailto:`, etc.)
+/// are returned unchanged.
+fn normalize_input(arg: &str) -> String {
+    let url = file_path_to_url(arg.to_owned());
+    // A relative path to an existing file, such as `index.html`, looks like a host as well
+    if is_bare_host(&url) && !Path::new(&url).exists() {
+        debug_log!("Treating '{url}' as an https URL, since it has no scheme");
+        return format!("https://{url}");
+    }
+    url
+}
+
+/// Whether `input` starts with a host (optionally followed by a port and a path) but no scheme.
+fn is_bare_host(input: &str) -> bool {
+    let authority = input.split(['/', '?', '#']).next().unwrap_or_default();
+    // What comes before a colon is a scheme, unless it's followed by a port
+    let host = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.is_empty() && port.bytes().all(|it| it.is_ascii_digit()) => host,
+        Some(_) => return false,
+        None => authority,
+    };
+    let is_host_char = |it: char| it.is_alphanumeric() || it == '-' || it == '.';
+    !host.is_empty()
+        && !host.starts_with(['.', '-'])
+        && host.chars().all(is_host_char)
+        && (host.contains('.') || host.eq_ignore_ascii_case("localhost"))
+}
+
+/// The shell passes files opened with FirefoxRouter (HTML, PDF) as local paths rather than URLs.
+/// Absolute paths (`C:\dir\page.html`, UNC `\\server\share\page.html` or `/dir/page.html`) are
+/// converted to `file://` URLs, so only `file://` globs can filter or route them and Firefox still
+/// gets something it can open. Anything else is returned unchanged.
+fn file_path_to_url(arg: String) -> String {
+    if arg.contains("://") {
+        return arg;
+    }
+    let bytes = arg.as_bytes();
+    let is_drive_path = bytes.len() >= 3
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && matches!(bytes[2], b'\\' | b'/');
+    let url = if is_drive_path {
+        format!("file:///{}", percent_encode_path(&arg.replace('\\', "/")))
+    } else if let Some(unc_path) = arg.strip_prefix(r"\\") {
+        format!("file://{}", percent_encode_path(&unc_path.replace('\\', "/")))
+    } else if arg.starts_with('/') && !arg.starts_with("//") {
+        format!("file://{}", percent_encode_path(&arg))
+    } else {
+        return arg;
+    };
+    debug_log!("Converted local path '{arg}' to URL '{url}'");
+    url
+}
+
+/// Runs the rewrite rules in order over `url`, each one receiving the output of the previous rule.
+/// Only the first match of each rule is replaced, and `$1`-style references in the replacement
+/// expand to the rule's capture groups.
+fn apply_rewrites(url: &str, config: Option<&AppConfig>) -> String {
+    let Some(config) = config else {
+        return url.to_owned();
+    };
+    let rewritten = config.rewrite_rules.iter().fold(url.to_owned(), |url, rule| {
+        rule.regex.as_ref().replace(&url, rule.replace.as_str()).into_owned()
+    });
+    if rewritten != url {
+        debug_log!("Rewrote URL '{url}' to '{rewritten}' due to configured rewrite rules");
+    }
+    rewritten
+}
+
+/// Removes the query parameters whose key is in `keys` (case-insensitively) from `url`, keeping the
+/// remaining ones in order and dropping the `?` altogether when no parameter is left.
+fn clean_query(url: &str, keys: &[String]) -> String {
+    if keys.is_empty() {
+        return url.to_owned();
+    }
+    let Some((base, rest)) = url.split_once('?') else {
+        return url.to_owned();
+    };
+    let (query, fragment) = match rest.split_once('#') {
+        Some((query, fragment)) => (query, Some(fragment)),
+        None => (rest, None),
+    };
+
+    let params: Vec<&str> = query.split('&')
+        .filter(|param| {
+            let key = param.split_once('=').map_or(*param, |(key, _)| key);
+            !keys.iter().any(|it| it.eq_ignore_ascii_case(key))
+        })
+        .collect();
+
+    let mut cleaned = base.to_owned();
+    if !params.is_empty() {
+        cleaned.push('?');
+        cleaned.push_str(&params.join("&"));
+    }
+    if let Some(fragment) = fragment {
+        cleaned.push('#');
+        cleaned.push_str(fragment);
+    }
+    if cleaned != url {
+        debug_log!("Stripped query parameters from URL '{url}', result '{cleaned}'");
+    }
+    cleaned
+}
+
+/// Returns the first browser rule matching `url`, if any.
+fn resolve_browser_rule<'a>(url: &str, config: Option<&'a AppConfig>) -> Option<&'a BrowserRule> {
+    let config = config?;
+    match matching_rule(url, config)? {
+        (MatchedRule::Browser(index), _) => Some(&config.browser_rules[index]),
+        (MatchedRule::Profile(_) | MatchedRule::ProfileRegex(_), _) => None,
+    }
+}
+
+/// Returns the route of the profile rule deciding where `url` opens. URLs matching no rule get the
+/// default route, which opens them normally in the active profile.
+fn resolve_route(url: &str, config: Option<&AppConfig>) -> Route {
+    let Some(config) = config else {
+        return Route::default();
+    };
+    match matching_rule(url, config) {
+        Some((MatchedRule::Profile(index), _)) => profile_rule_route(&config.profile_rules[index]),
+        Some((MatchedRule::ProfileRegex(index), _)) => profile_regex_rule_route(&config.profile_rules_regex[index]),
+        Some((MatchedRule::Browser(_), _)) => Route::default(),
+        None => matching_scheme_rule(url, config)
+            .map(|index| scheme_rule_route(&config.scheme_rules[index]))
+            .unwrap_or_default(),
+    }
+}
+
+/// Returns the scheme rule deciding what happens to `url`, which only applies when no browser or
+/// profile rule matched it.
+fn resolve_scheme_rule<'a>(url: &str, config: Option<&'a AppConfig>) -> Option<&'a SchemeRule> {
+    let config = config?;
+    if matching_rule(url, config).is_some() {
+        return None;
+    }
+    matching_scheme_rule(url, config).map(|index| &config.scheme_rules[index])
+}
+
+/// The index of the first scheme rule for the scheme of `url`, regardless of the other rules.
+fn matching_scheme_rule(url: &str, config: &AppConfig) -> Option<usize> {
+    let scheme = url_scheme(url)?;
+    config.scheme_rules.iter().position(|it| it.scheme.eq_ignore_ascii_case(scheme))
+}
+
+fn scheme_rule_route(rule: &SchemeRule) -> Route {
+    match &rule.action {
+        SchemeAction::Profile(profile) => Route { profile_name: Some(profile.clone()), ..Default::default() },
+        SchemeAction::Ignore | SchemeAction::DefaultBrowser => Route::default(),
+    }
+}
+
+fn profile_rule_route(rule: &ProfileRule) -> Route {
+    Route {
+        profile_name: rule.profile.clone(),
+        profile_pool: Some(ProfilePool { profiles: rule.profiles.clone(), strategy: rule.strategy })
+            .filter(|it| rule.profile.is_none() && !it.profiles.is_empty()),
+        action: rule.action.clone(),
+    }
+}
+
+fn profile_regex_rule_route(rule: &ProfileRegexRule) -> Route {
+    Route {
+        profile_name: Some(rule.profile.clone()),
+        profile_pool: None,
+        action: rule.action.clone(),
+    }
+}
+
+/// The priority of the ignore and allow rules, which rules with a higher priority override.
+const FILTER_PRIORITY: i32 = 0;
+
+/// A browser or profile rule, by its index in the config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MatchedRule {
+    Browser(usize),
+    Profile(usize),
+    ProfileRegex(usize),
+}
+
+impl std::fmt::Display for MatchedRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MatchedRule::Browser(index) => write!(f, "browser_rules[{index}]"),
+            MatchedRule::Profile(index) => write!(f, "profile_rules[{index}]"),
+            MatchedRule::ProfileRegex(index) => write!(f, "profile_rules_regex[{index}]"),
+        }
+    }
+}
+
+/// Returns the rule deciding where `url` opens along with its priority: the matching browser or
+/// profile rule with the highest priority, ties going to browser rules, then to the glob-based profile
+/// rules and then to the first rule.
+fn matching_rule(url: &str, config: &AppConfig) -> Option<(MatchedRule, i32)> {
+    let browser_rules = config.browser_rules.iter()
+        .enumerate()
+        .filter(|(_, rule)| rule.pattern.is_match(url))
+        .map(|(index, rule)| (MatchedRule::Browser(index), rule.priority));
+    let profile_rules = config.profile_rules.iter()
+        .enumerate()
+        .filter(|(_, rule)| rule.pattern.is_match(url))
+        .map(|(index, rule)| (MatchedRule::Profile(index), rule.priority));
+    let profile_regex_rules = config.profile_rules_regex.iter()
+        .enumerate()
+        .filter(|(_, rule)| rule.regex.as_ref().is_match(url))
+        .map(|(index, rule)| (MatchedRule::ProfileRegex(index), rule.priority));
+    // `min_by_key` keeps the first of the equal elements, unlike `max_by_key`
+    browser_rules.chain(profile_rules)
+        .chain(profile_regex_rules)
+        .min_by_key(|(_, priority)| std::cmp::Reverse(*priority))
+}
+
+/// Groups URLs by the route resolved from the profile rules, keeping the order in which each route
+/// was first seen, so each group can be opened with a single Firefox invocation.
+fn group_by_route(
+    config: Option<&AppConfig>,
+    urls: Vec<String>,
+) -> Vec<(Route, Vec<String>)> {
+    let mut groups: Vec<(Route, Vec<String>)> = Vec::new();
+    for url in urls {
+        let route = resolve_route(&url, config);
+        match groups.iter_mut().find(|(it, _)| *it == route) {
+            Some((_, group)) => group.push(url),
+            None => groups.push((route, vec![url])),
+        }
+    }
+    groups
+}
+
+/// The profile to open URLs in: the one their route resolved to, otherwise the active Firefox's and
+/// then the configured `default_profile`. `None` leaves it up to Firefox (or `profiles.ini`).
+fn resolve_profile(
+    routed_profile: Option<ProfileRef>,
+    active_firefox: Option<&FirefoxInfo>,
+    config: Option<&AppConfig>,
+) -> Option<ProfileRef> {
+    routed_profile
+        .or_else(|| active_firefox.and_then(|it| it.profile.clone()))
+        .or_else(|| config.and_then(|it| it.default_profile.clone()).map(ProfileRef::Named))
+}
+
+/// URLs opened with a single Firefox invocation.
+#[derive(Debug, PartialEq, Eq)]
+struct LaunchGroup {
+    /// The profile to open the URLs in, the active Firefox's (or the default profile) when `None`.
+    profile: Option<ProfileRef>,
+    action: RuleAction,
+    urls: Vec<String>,
+}
+
+/// Adds `urls` to the group with the same profile and action, so routes resolving to the same
+/// profile (e.g. a profile rule naming the active profile) still share a Firefox invocation.
+fn add_to_group(groups: &mut Vec<LaunchGroup>, profile: Option<ProfileRef>, action: RuleAction, urls: Vec<String>) {
+    match groups.iter_mut().find(|it| it.profile == profile && it.action == action) {
+        Some(group) => group.urls.extend(urls),
+        None => groups.push(LaunchGroup { profile, action, urls }),
+    }
+}
+
+/// Spawns one Firefox per group, with all of its URLs, and returns the profiles URLs were opened in.
+/// With a `startup_timeout`, the first launch is given that long to start Firefox before launching
+/// the other groups, which are then handed to it instead of starting competing instances.
+fn launch_grouped(
+    groups: Vec<LaunchGroup>,
+    active_firefox: Option<&FirefoxInfo>,
+    startup_timeout: Option<Duration>,
+    config: Option<&AppConfig>,
+    launcher: &dyn Launcher,
+    processes: &dyn ProcessSource,
+    history: &mut History,
+) -> Result<Vec<ProfileRef>> {
+    let firefox_path = &resolve_firefox_path(config, active_firefox);
+    let open_mode = config.map(|it| it.open_mode).unwrap_or_default();
+    // Before splitting, so the URLs of a group still share a single new container
+    let groups = split_long_groups(groups.into_iter().map(with_ephemeral_container).collect());
+    let group_count = groups.len();
+    let mut opened_profiles = Vec::new();
+    let mut started_firefox = None;
+    for (index, group) in groups.into_iter().enumerate() {
+        let is_temp_profile = group.action == RuleAction::TempProfile;
+        let profile = match is_temp_profile {
+            true => match make_temp_profile(launcher.is_dry_run()) {
+                Ok(path) => Some(ProfileRef::Path(path)),
+                Err(e) => {
+                    group.urls.iter().for_each(|url| history.record(url, HistoryOutcome::Failed, None));
+                    return Err(e);
+                },
+            },
+            false => group.profile,
+        };
+        let routed_firefox = profile.map(|profile| FirefoxInfo {
+            path: firefox_path.to_string_lossy().into_owned(),
+            profile: Some(profile),
+            start_time: 0,
+        });
+        let firefox_info = routed_firefox.as_ref().or(started_firefox.as_ref()).or(active_firefox);
+        match open_with_firefox(&group.urls, firefox_path, firefox_info, &group.action, open_mode, spawn_retries(config), launcher) {
+            Ok(launched_profile) => {
+                group.urls.iter().for_each(|url| history.record(url, HistoryOutcome::Firefox, launched_profile.as_ref()));
+                opened_profiles.extend(firefox_info.and_then(|it| it.profile.clone()));
+            },
+            Err(e) => {
+                let result = handle_firefox_error(e, &group.urls, firefox_path, config, launcher);
+                let outcome = fallback_outcome(config);
+                group.urls.iter().for_each(|url| history.record_launch(url, &result, outcome, None));
+                result?;
+                continue;
+            }
+        }
+        // The other groups can't be handed to a Firefox using a temporary profile
+        if index == 0 && group_count > 1 && !is_temp_profile && let Some(timeout) = startup_timeout {
+            debug_log!("Waiting for the launched Firefox to start before opening the other URLs");
+            started_firefox = wait_for_firefox(processes, timeout);
+            if started_firefox.is_none() {
+                debug_log!("Firefox didn't start within {timeout:?}, opening the other URLs anyway");
+            }
+        }
+    }
+    Ok(opened_profiles)
+}
+
+/// The total length of the URLs passed to a single Firefox, to stay below Windows' limit of 32767
+/// characters for a whole command line with room for the executable, the profile and the flags.
+const MAX_URLS_LENGTH: usize = 28_000;
+/// The flag and separators each URL is passed with, e.g. ` -private-window "<url>"`.
+const URL_ARG_OVERHEAD: usize = 20;
+
+/// Splits the groups with too many URLs to fit in a single command line into several groups with
+/// the same profile and action, launched one after the other.
+fn split_long_groups(groups: Vec<LaunchGroup>) -> Vec<LaunchGroup> {
+    groups.into_iter()
+        .flat_map(|group| {
+            // Container and reader mode URLs are wrapped and percent-encoded, which can triple their length
+            let max_len = match group.action {
+                RuleAction::Container(_) | RuleAction::ReaderMode => MAX_URLS_LENGTH / 3,
+                _ => MAX_URLS_LENGTH,
+            };
+            let chunks = chunk_urls(group.urls, max_len);
+            if chunks.len() > 1 {
+                debug_log!("Splitting the URLs into {} Firefox invocations to fit in the command line", chunks.len());
+            }
+            chunks.into_iter().map(move |urls| LaunchGroup { profile: group.profile.clone(), action: group.action.clone(), urls })
+        })
+        .collect()
+}
+
+/// Splits `urls` into chunks whose URLs, along with their flags, add up to at most `max_len`
+/// characters, keeping their order. A URL longer than that on its own gets a chunk of its own.
+fn chunk_urls(urls: Vec<String>, max_len: usize) -> Vec<Vec<String>> {
+    let mut chunks: Vec<Vec<String>> = Vec::new();
+    let mut chunk_len = 0;
+    for url in urls {
+        let len = url.len() + URL_ARG_OVERHEAD;
+        match chunks.last_mut() {
+            Some(chunk) if chunk_len + len <= max_len => {
+                chunk.push(url);
+                chunk_len += len;
+            },
+            _ => {
+                chunks.push(vec![url]);
+                chunk_len = len;
+            },
+        }
+    }
+    chunks
+}
+
+/// Gives a group opened in an ephemeral container a container of its own, named after the rule's
+/// container and this launch.
+fn with_ephemeral_container(group: LaunchGroup) -> LaunchGroup {
+    match group.action {
+        RuleAction::Container(Container { name, mode: ContainerMode::Ephemeral }) => LaunchGroup {
+            action: RuleAction::Container(Container::named(format!("{name}-{}", launch_id()))),
+            ..group
+        },
+        _ => group,
+    }
+}
+
+/// Identifies this launch among the others, for naming the profiles and containers it creates.
+fn launch_id() -> String {
+    let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|it| it.as_nanos()).unwrap_or_default();
+    format!("{}-{nanos}", std::process::id())
+}
+
+/// Creates an empty folder in the temp folder for a throwaway profile, which Firefox fills on startup.
+/// FirefoxRouter exits right after launching Firefox, so removing it is left to the OS's cleanup of
+/// the temp folder. With `dry_run`, the folder isn't created.
+fn make_temp_profile(dry_run: bool) -> Result<PathBuf> {
+    let path = std::env::temp_dir().join(format!("FirefoxRouter-profile-{}", launch_id()));
+    if !dry_run {
+        std::fs::create_dir_all(&path).map_err(|e| eyre!("Couldn't create temporary profile {}: {e}", path.display()))?;
+        debug_log!("Created temporary profile {}", path.display());
+    }
+    Ok(path)
+}
+
+/// How long to wait for a just launched Firefox to show up, capped so a click never hangs for long.
+const FIREFOX_STARTUP_TIMEOUT: Duration = Duration::from_secs(5);
+const FIREFOX_STARTUP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Waits for a Firefox process to be running, returning `None` if none started within `timeout`.
+fn wait_for_firefox(processes: &dyn ProcessSource, timeout: Duration) -> Option<FirefoxInfo> {
+    poll_until(timeout, FIREFOX_STARTUP_POLL_INTERVAL, || processes.firefox_processes().into_iter().min())
+}
+
+/// Calls `f` every `interval` until it returns something, giving up after `timeout`.
+fn poll_until<T>(timeout: Duration, interval: Duration, mut f: impl FnMut() -> Option<T>) -> Option<T> {
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        if let Some(value) = f() {
+            return Some(value);
+        }
+        if std::time::Instant::now() + interval > deadline {
+            return None;
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+/// Whether a browser or profile rule with a higher priority than the filtering rules matches `url`,
+/// once rewritten the way it would be opened.
+fn is_filtering_overridden(url: &str, config: &AppConfig) -> bool {
+    let final_url = clean_query(&apply_rewrites(url, Some(config)), &config.strip_query_params);
+    matching_rule(&final_url, config).is_some_and(|(_, priority)| priority > FILTER_PRIORITY)
+}
+
+fn filter_args(
+    config: Option<&AppConfig>,
+    args: impl IntoIterator<Item = impl AsRef<str>>,
+) -> Result<Vec<String>> {
+    let args: Vec<String> = args.into_iter().map(|s| s.as_ref().to_owned()).collect();
+    let Some(config) = config else {
+        debug_log!("No config file found, not filtering URLs");
+        return Ok(dedupe_urls(args));
+    };
+
+    let args = filter_url_lengths(config, args);
+    let filtered_args: Vec<_> = args.iter().filter(|&url| {
+        let is_ignored = matches_any(&config.ignored_urls, url).is_some()
+            || config.ignored_urls_regex.iter().any(|it| it.as_ref().is_match(url));
+        // Ignore rules take precedence, the allowlist is only enforced when it's not empty
+        let is_allowed = config.allowed_urls.is_empty() || matches_any(&config.allowed_urls, url).is_some();
+        (!is_ignored && is_allowed) || is_filtering_overridden(url, config)
+    }).cloned().collect();
+
+    if filtered_args.len() != args.len() {
+        debug_log!(
+            "Removed {} URLs from the list due to configured URL filtering rules ({} -> {})",
+            args.len() - filtered_args.len(),
+            args.len(),
+            filtered_args.len()
+        );
+    }
+    if config.dedupe_urls {
+        return Ok(dedupe_urls(filtered_args));
+    }
+    Ok(filtered_args)
+}
+
+/// Removes the URLs whose length is outside the `min_url_len`/`max_url_len` bounds, in characters.
+fn filter_url_lengths(config: &AppConfig, urls: Vec<String>) -> Vec<String> {
+    if config.min_url_len.is_none() && config.max_url_len.is_none() {
+        return urls;
+    }
+    let count = urls.len();
+    let (min, max) = (config.min_url_len.unwrap_or(0), config.max_url_len.unwrap_or(usize::MAX));
+    let kept: Vec<String> = urls.into_iter()
+        .filter(|url| (min..=max).contains(&url.chars().count()))
+        .collect();
+    if kept.len() != count {
+        debug_log!("Removed {} URLs from the list due to their length ({} -> {})", count - kept.len(), count, kept.len());
+    }
+    kept
+}
+
+/// Removes the URLs that were already passed earlier, keeping the order of their first occurrence.
+fn dedupe_urls(urls: Vec<String>) -> Vec<String> {
+    let count = urls.len();
+    let mut deduped: Vec<String> = Vec::with_capacity(count);
+    for url in urls {
+        if !deduped.contains(&url) {
+            deduped.push(url);
+        }
+    }
+    if deduped.len() != count {
+        debug_log!("Removed {} duplicate URLs from the list", count - deduped.len());
+    }
+    deduped
+}
+
+/// Evaluates `globs` in order, so the last glob matching `url` decides the outcome: a positive glob
+/// matches the URL, while a negated one (`!https://...`) excludes it again. For `ignored_urls` this
+/// means a URL is ignored only if it matches a positive glob and no later negated glob un-ignores it.
+/// Returns the index of the glob that matched the URL, if any.
+fn matches_any(globs: &[Glob], url: &str) -> Option<usize> {
+    globs.iter()
+        .enumerate()
+        .filter(|(_, it)| it.is_match(url))
+        .last()
+        .filter(|(_, it)| !it.is_negated())
+        .map(|(index, _)| index)
+}
+
+/// Returns the executable names recognized as Firefox, the defaults followed by the ones added in
+/// the config.
+fn firefox_executables(config: Option<&AppConfig>) -> Vec<&str> {
+    let configured = config.iter().flat_map(|it| it.firefox_executables.iter().map(String::as_str));
+    DEFAULT_FIREFOX_EXECUTABLES.into_iter().chain(configured).collect()
+}
+
+/// Finds the running Firefox processes, which tests replace with a fixed list.
+pub trait ProcessSource {
+    /// The Firefox processes running right now, in no particular order.
+    fn firefox_processes(&self) -> Vec<FirefoxInfo>;
+
+    /// The Firefox owning the frontmost Firefox window, which is the one the user looked at last.
+    fn frontmost_firefox(&self) -> Option<FirefoxInfo> {
+        None
+    }
+}
+
+/// Reads the running processes through `sysinfo`, taking a new snapshot on each call.
+struct SystemProcesses<'a> {
+    firefox_executables: Vec<&'a str>,
+    /// Only read for the processes started without a profile argument.
+    installed_profiles: LazyCell<Vec<FirefoxProfile>>,
+}
+
+impl<'a> SystemProcesses<'a> {
+    fn new(config: Option<&'a AppConfig>) -> Self {
+        Self {
+            firefox_executables: firefox_executables(config),
+            installed_profiles: LazyCell::new(list_profiles),
+        }
+    }
+}
+
+impl ProcessSource for SystemProcesses<'_> {
+    fn firefox_processes(&self) -> Vec<FirefoxInfo> {
+        firefox_candidate_processes().processes().values()
+            .filter(|it| is_firefox_process(it, &self.firefox_executables))
+            .filter_map(|it| get_firefox_info_or_locked(it, &self.installed_profiles))
+            .collect()
+    }
+
+    /// Only available on Windows. Each window's process is looked up on its own, as the frontmost
+    /// windows are usually found long before going through every process.
+    fn frontmost_firefox(&self) -> Option<FirefoxInfo> {
+        let mut sys = System::new();
+        let mut firefox_process = |pid: u32| {
+            let pid = Pid::from_u32(pid);
+            sys.refresh_processes_specifics(
+                ProcessesToUpdate::Some(&[pid]),
+                true,
+                ProcessRefreshKind::nothing().with_cmd(UpdateKind::Always),
+            );
+            sys.process(pid).is_some_and(|it| is_firefox_process(it, &self.firefox_executables))
+        };
+        let pid = win::frontmost_window_pid(&mut firefox_process)?;
+        sys.process(Pid::from_u32(pid)).and_then(|it| get_firefox_info_or_locked(it, &self.installed_profiles))
+    }
+}
+
+/// Only the command line of the processes is needed, so skip everything else `System::new_all` would
+/// gather (CPU, memory, disks, etc.). With ~70 processes running this takes ~1ms instead of ~2.7ms
+/// on Linux, which adds up since it runs on every link click.
+fn firefox_candidate_processes() -> System {
+    let mut sys = System::new();
+    sys.refresh_processes_specifics(
+        ProcessesToUpdate::All,
+        true,
+        ProcessRefreshKind::nothing().with_cmd(UpdateKind::Always),
+    );
+    sys
+}
+
+fn is_firefox_process(it: &Process, executables: &[&str]) -> bool {
+    it.cmd().first()
+        .is_some_and(|s| is_firefox_executable(s, executables))
+}
+
+fn is_firefox_executable(path: impl AsRef<OsStr>, executables: &[&str]) -> bool {
+    Path::new(path.as_ref())
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| executables.iter().any(|it| name.eq_ignore_ascii_case(it)))
+}
+
+/// Like [`get_firefox_info`], but a Firefox started without a profile argument (as when launched
+/// normally) gets the profile whose lock it holds, `installed_profiles` only being read then.
+fn get_firefox_info_or_locked(it: &Process, installed_profiles: &LazyCell<Vec<FirefoxProfile>>) -> Option<FirefoxInfo> {
+    let mut info = get_firefox_info(it)?;
+    if info.profile.is_none() && let Some(profile) = locked_profile(it.pid().as_u32(), installed_profiles) {
+        debug_log!("Found profile '{}' through its lock file", profile.name);
+        info.profile = Some(ProfileRef::Named(profile.name.clone()));
+    }
+    Some(info)
+}
+
+fn get_firefox_info(it: &Process) -> Option<FirefoxInfo> {
+    let cmd = it.cmd();
+    if cmd.is_empty() {
+        debug_log!("Attempted to get Firefox info for a process with no command line arguments");
+        return None;
+    }
+
+    let path = cmd.first().map(|s| s.to_string_lossy()).unwrap().into_owned();
+    let profile = get_profile_arg(cmd);
+
+    Some(FirefoxInfo {
+        path,
+        profile,
+        start_time: it.start_time(),
+    })
+}
+
+fn get_profile_arg(cmd: &[impl AsRef<OsStr>]) -> Option<ProfileRef> {
+    cmd.iter()
+        .map(|s| s.as_ref())
+        .skip_while(|&s| s != "-P" && s != "-profile")
+        .nth(1)
+        .map(|s| ProfileRef::from_arg(&s.to_string_lossy()))
+}
+
+/// Opens `args` with the Firefox at `firefox_path`, in the profile of `firefox_info` or, when there's
+/// no Firefox running, in the default profile from profiles.ini. Returns the profile it was opened in.
+fn open_with_firefox(
+    args: &[String],
+    firefox_path: &Path,
+    firefox_info: Option<&FirefoxInfo>,
+    action: &RuleAction,
+    open_mode: OpenMode,
+    spawn_retries: u32,
+    launcher: &dyn Launcher,
+) -> std::io::Result<Option<ProfileRef>> {
+    let profile = match firefox_info {
+        Some(info) => info.profile.clone(),
+        None => find_default_profile().map(|it| {
+            debug_log!("Using default profile from profiles.ini: {} ({})", it.name, it.path.display());
+            ProfileRef::Named(it.name)
+        }),
+    };
+    let profile_display = profile.as_ref().map(|it| it.to_string());
+    if launcher.is_dry_run() {
+        log!("Firefox: {}, profile: {}", firefox_path.display(), profile_display.as_deref().unwrap_or("<none>"));
+    } else {
+        debug_log!("Using Firefox at: {}, profile: {}", firefox_path.display(), profile_display.as_deref().unwrap_or("<none>"));
+    }
+
+    with_spawn_retries(spawn_retries, SPAWN_RETRY_DELAY, || {
+        launcher.launch(&mut firefox_command(args, firefox_path, profile.clone(), action, open_mode))
+    })?;
+    Ok(profile)
+}
+
+/// Delay before retrying a failed launch, doubled on each retry.
+const SPAWN_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Runs `spawn` until it succeeds, retrying up to `retries` times when it fails with a transient error
+/// and waiting `delay` in between, doubled after each retry.
+fn with_spawn_retries(
+    retries: u32,
+    mut delay: Duration,
+    mut spawn: impl FnMut() -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let mut attempt = 0;
+    loop {
+        match spawn() {
+            Err(e) if attempt < retries && is_transient_spawn_error(&e) => {
+                attempt += 1;
+                warn!("Launching failed ({e}), retrying in {delay:?} (attempt {attempt} of {retries})");
+                std::thread::sleep(delay);
+                delay *= 2;
+            },
+            result => return result,
+        }
+    }
+}
+
+/// Errors that may go away by themselves, such as the executable being replaced by an update, unlike
+/// a missing executable or a denied access.
+fn is_transient_spawn_error(error: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+
+    // Windows' ERROR_SHARING_VIOLATION, such as while an update replaces firefox.exe, has no kind of its own
+    #[cfg(windows)]
+    if error.raw_os_error() == Some(32) {
+        return true;
+    }
+    matches!(
+        error.kind(),
+        ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut
+            | ErrorKind::ResourceBusy | ErrorKind::ExecutableFileBusy
+    )
+}
+
+fn spawn_retries(config: Option<&AppConfig>) -> u32 {
+    config.map_or(DEFAULT_SPAWN_RETRIES, |it| it.spawn_retries)
+}
+
+/// Builds the Firefox invocation opening `urls`. Each URL is passed as its own argument, which
+/// `Command` quotes as needed, so spaces, quotes, `&` and `%` reach Firefox unchanged.
+fn firefox_command(
+    urls: &[String],
+    firefox_path: &Path,
+    profile: Option<ProfileRef>,
+    action: &RuleAction,
+    open_mode: OpenMode,
+) -> Command {
+    let mut command = Command::new(firefox_path);
+    match profile {
+        Some(ProfileRef::Named(name)) => command.arg("-P").arg(name),
+        Some(ProfileRef::Path(path)) => command.arg("-profile").arg(path),
+        None => &mut command,
+    };
+    // Before the URLs, as each URL flag must be directly followed by its URL
+    let open_mode = match action {
+        RuleAction::Launch { extra_args, open_mode: rule_open_mode } => {
+            command.args(extra_args);
+            rule_open_mode.unwrap_or(open_mode)
+        },
+        _ => open_mode,
+    };
+    // Firefox takes one URL per flag, so each one gets its own flag to batch them all in one invocation
+    let url_flag = match (action, open_mode) {
+        (RuleAction::PrivateWindow, _) => "-private-window",
+        (_, OpenMode::Url) => "-url",
+        (_, OpenMode::NewTab) => "-new-tab",
+        (_, OpenMode::NewWindow) => "-new-window",
+    };
+    for url in urls {
+        match action {
+            RuleAction::Container(container) => command.arg(url_flag).arg(container_url(&container.name, url)),
+            RuleAction::ReaderMode => command.arg(url_flag).arg(reader_url(url)),
+            _ => command.arg(url_flag).arg(url),
+        };
+    }
+    command
+}
+
+/// Opens `urls` with the fallback browser when Firefox couldn't be launched, only showing the error
+/// to the user if there's no fallback browser or it couldn't be launched either.
+fn handle_firefox_error(
+    error: std::io::Error,
+    urls: &[String],
+    firefox_path: &Path,
+    config: Option<&AppConfig>,
+    launcher: &dyn Launcher,
+) -> std::io::Result<()> {
+    debug_log!("Couldn't launch Firefox at '{}': {error}", firefox_path.display());
+    match open_with_fallback(urls, config, launcher) {
+        Some(Ok(())) => {
+            debug_log!("Opened {} URLs with the fallback browser", urls.len());
+            return Ok(());
+        },
+        Some(Err(e)) => debug_log!("Couldn't launch the fallback browser either: {e}"),
+        None => {},
+    }
+    win::show_error(&format!(
+        "Couldn't launch Firefox at '{}': {error}\n\nMake sure Firefox is installed, or that its folder is in the PATH.",
+        firefox_path.display(),
+    ));
+    Err(error)
+}
+
+/// Where [`handle_firefox_error`] opens the URLs when Firefox couldn't be launched, for the history.
+fn fallback_outcome(config: Option<&AppConfig>) -> HistoryOutcome {
+    match config.and_then(|it| it.fallback_command.as_ref()) {
+        Some(_) => HistoryOutcome::Browser,
+        None => HistoryOutcome::DefaultBrowser,
+    }
+}
+
+/// Set on the processes launched as a fallback, so if FirefoxRouter is the default browser and gets
+/// launched by the fallback, it doesn't fall back into itself again.
+const FALLBACK_ENV_VAR: &str = "FIREFOX_ROUTER_FALLBACK";
+
+/// Opens each URL with the fallback browser, returning `None` if there's none to use.
+fn open_with_fallback(urls: &[String], config: Option<&AppConfig>, launcher: &dyn Launcher) -> Option<std::io::Result<()>> {
+    if std::env::var_os(FALLBACK_ENV_VAR).is_some() {
+        debug_log!("Launched as a fallback browser, not falling back again");
+        return None;
+    }
+    let commands = fallback_commands(urls, config?)?;
+    Some(commands.into_iter().try_for_each(|mut it| launcher.launch(&mut it)))
+}
+
+/// The `fallback_command` takes precedence over the system's default browser.
+fn fallback_commands(urls: &[String], config: &AppConfig) -> Option<Vec<Command>> {
+    if config.fallback_command.is_none() && !config.fallback_to_default_browser {
+        return None;
+    }
+    let commands = urls.iter()
+        .map(|url| {
+            let mut command = match &config.fallback_command {
+                Some(program) => {
+                    let mut command = Command::new(program);
+                    command.args(command_args(url, &config.fallback_args));
+                    command
+                },
+                None => default_opener_command(url),
+            };
+            command.env(FALLBACK_ENV_VAR, "1");
+            command
+        })
+        .collect();
+    Some(commands)
+}
+
+/// Opens each URL with the system's default browser, returning `None` if that's FirefoxRouter
+/// itself (`is_own_default`), which would route them right back here.
+fn default_browser_commands(urls: &[String], is_own_default: bool) -> Option<Vec<Command>> {
+    if is_own_default {
+        return None;
+    }
+    let commands = urls.iter()
+        .map(|url| {
+            let mut command = default_opener_command(url);
+            command.env(FALLBACK_ENV_VAR, "1");
+            command
+        })
+        .collect();
+    Some(commands)
+}
+
+/// Whether the system's default browser is FirefoxRouter, either as registered or because it was
+/// launched as a fallback browser.
+fn is_default_browser() -> bool {
+    std::env::var_os(FALLBACK_ENV_VAR).is_some()
+        || ["http", "https"].into_iter().any(|it| default_handler(it).as_deref() == Some(URL_HANDLER_ID))
+}
+
+/// What the scheme rule `action` does with `url`. A `default_browser` one opens it in Firefox instead
+/// when that would route it right back here, `is_own_default` telling whether FirefoxRouter is the
+/// default browser. Shared by the routing and [`explain_match`], so `resolve` and `--test-url` agree
+/// with what opening the URL does.
+fn scheme_action<'a>(url: &str, action: Option<&'a SchemeAction>, is_own_default: impl FnOnce() -> bool) -> Option<&'a SchemeAction> {
+    match action {
+        Some(SchemeAction::DefaultBrowser) if is_routed_back(url, is_own_default()) => {
+            warn!("Not handing '{url}' to the system's default browser as it's FirefoxRouter itself, opening it in Firefox");
+            None
+        },
+        action => action,
+    }
+}
+
+/// Whether handing `url` to the system's default browser would route it right back here, given
+/// whether that's FirefoxRouter (`is_own_default`).
+fn is_routed_back(url: &str, is_own_default: bool) -> bool {
+    let is_web_url = url_scheme(url).is_some_and(|it| it.eq_ignore_ascii_case("http") || it.eq_ignore_ascii_case("https"));
+    is_web_url && is_own_default
+}
+
+/// Opens `url` with the app the system associates with its scheme, e.g. the default browser.
+fn default_opener_command(url: &str) -> Command {
+    let mut command = Command::new(DEFAULT_BROWSER_OPENER[0]);
+    command.args(&DEFAULT_BROWSER_OPENER[1..]).arg(url);
+    command
+}
+
+/// Wraps `url` into the `ext+container:` scheme handled by the "Open external links in a container"
+/// add-on, so it's opened in the container called `name`.
+fn container_url(name: &str, url: &str) -> String {
+    format!("ext+container:name={}&url={}", percent_encode(name), percent_encode(url))
+}
+
+/// Wraps `url` into Firefox's reader mode page, which shows it without the clutter around the content.
+fn reader_url(url: &str) -> String {
+    format!("about:reader?url={}", percent_encode(url))
+}
+
+fn open_with_browser(url: &str, rule: &BrowserRule, launcher: &dyn Launcher) -> std::io::Result<()> {
+    let mut command = Command::new(&rule.command);
+    command.args(browser_args(url, rule));
+    launcher.launch(&mut command)
+}
+
+/// Builds the arguments for a browser rule, replacing every `{url}` placeholder with `url`. If no
+/// argument contains the placeholder, the URL is appended as the last argument instead.
+fn browser_args(url: &str, rule: &BrowserRule) -> Vec<String> {
+    command_args(url, &rule.args)
+}
+
+/// Substitutes `{url}` in `args`, or appends the URL if there's no placeholder.
+fn command_args(url: &str, args: &[String]) -> Vec<String> {
+    let mut command_args: Vec<String> = args.iter()
+        .map(|it| it.replace(URL_PLACEHOLDER, url))
+        .collect();
+    if !args.iter().any(|it| it.contains(URL_PLACEHOLDER)) {
+        command_args.push(url.to_owned());
+    }
+    command_args
+}
+
+/// Starts the programs URLs are handed to, which lets tests check the commands without launching
+/// anything. It takes whole commands rather than a program and its args, since fallback commands
+/// also carry an env variable.
+pub trait Launcher {
+    fn launch(&self, command: &mut Command) -> std::io::Result<()>;
+
+    /// Whether the commands aren't really run, in which case nothing waits for them to start nor asks
+    /// the user anything.
+    fn is_dry_run(&self) -> bool {
+        false
+    }
+
+    /// Whether the system's default browser is FirefoxRouter, so handing it web URLs would route them
+    /// right back here. Tests replace the check of the actual system.
+    fn is_default_browser(&self) -> bool {
+        is_default_browser()
+    }
+}
+
+struct ProcessLauncher;
+
+impl Launcher for ProcessLauncher {
+    fn launch(&self, command: &mut Command) -> std::io::Result<()> {
+        debug_log!("Spawning: {}", format_command(command));
+        command.spawn().map(|_| ())
+    }
+}
+
+/// Prints the commands instead of running them, for `--dry-run`.
+struct DryRunLauncher;
+
+impl Launcher for DryRunLauncher {
+    fn launch(&self, command: &mut Command) -> std::io::Result<()> {
+        log!("Dry run, not spawning: {}", format_command(command));
+        Ok(())
+    }
+
+    fn is_dry_run(&self) -> bool {
+        true
+    }
+}
+
+/// Formats the program and arguments of `command`, quoting each one so the argument vector is unambiguous.
+fn format_command(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|it| format!("{:?}", it.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Picks the Firefox to launch. The `firefox_path` of the config is a hint used instead of looking
+/// for Firefox when it isn't running, unless `force_firefox_path` is set, in which case it's used
+/// even over the running Firefox.
+fn resolve_firefox_path(config: Option<&AppConfig>, running_firefox: Option<&FirefoxInfo>) -> PathBuf {
+    let configured_path = config.and_then(|it| it.firefox_path.as_deref()).map(PathBuf::from);
+    let is_forced = config.is_some_and(|it| it.force_firefox_path);
+    let path = match (configured_path, running_firefox) {
+        (Some(path), Some(_)) if is_forced => path,
+        (_, Some(info)) => return PathBuf::from(&info.path),
+        (Some(path), None) => path,
+        (None, None) => return find_firefox(),
+    };
+    if !path.exists() {
+        warn!("The configured firefox_path '{}' doesn't exist, trying to launch it anyway", path.display());
+    }
+    path
+}
+
+fn find_firefox() -> PathBuf {
+    #[cfg(windows)] {
+        use winreg::enums::HKEY_LOCAL_MACHINE;
+        use winreg::RegKey;
+
+        let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
+        let result = hklm.open_subkey(r"SOFTWARE\Microsoft\Windows\CurrentVersion\App Paths\firefox.exe")
+            .and_then(|it| it.get_value::<String, _>(""));
+        if let Ok(path) = result {
+            return PathBuf::from(path);
+        }
+    }
+
+    #[cfg(target_os = "linux")] {
+        let path_folders = std::env::var_os("PATH")
+            .map(|it| std::env::split_paths(&it).collect::<Vec<_>>())
+            .unwrap_or_default();
+        let known_folders = ["/usr/bin", "/usr/local/bin", "/snap/bin", "/usr/lib/firefox", "/opt/firefox"]
+            .map(PathBuf::from);
+        let found = path_folders.iter().chain(known_folders.iter())
+            .flat_map(|folder| ["firefox", "firefox-esr"].map(|name| folder.join(name)))
+            .find(|it| it.is_file());
+        if let Some(path) = found {
+            return path;
+        }
+    }
+
+    // Last resort: hope it's on PATH
+    PathBuf::from(if cfg!(windows) { "firefox.exe" } else { "firefox" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_folder;
+
+    fn config(json: &str) -> AppConfig {
+        serde_json::from_str(json).unwrap_or_else(|e| panic!("Failed to parse config '{json}': {e}"))
+    }
+
+    fn urls(urls: &[&str]) -> Vec<String> {
+        urls.iter().map(|it| it.to_string()).collect()
+    }
+
+    fn tracking_keys() -> Vec<String> {
+        urls(&["utm_source", "fbclid", "gclid"])
+    }
+
+    /// Records the commands instead of running them.
+    #[derive(Default)]
+    struct RecordingLauncher {
+        commands: std::cell::RefCell<Vec<Vec<String>>>,
+        /// Whether FirefoxRouter is taken for the system's default browser.
+        is_default_browser: bool,
+    }
+
+    impl Launcher for RecordingLauncher {
+        fn launch(&self, command: &mut Command) -> std::io::Result<()> {
+            let args = std::iter::once(command.get_program())
+                .chain(command.get_args())
+                .map(|it| it.to_string_lossy().into_owned())
+                .collect();
+            self.commands.borrow_mut().push(args);
+            Ok(())
+        }
+
+        fn is_dry_run(&self) -> bool {
+            true
+        }
+
+        fn is_default_browser(&self) -> bool {
+            self.is_default_browser
+        }
+    }
+
+    /// A config launching `/opt/firefox` in the `Personal` profile, unless a profile rule says otherwise,
+    /// without looking at the running Firefox.
+    fn launch_config(rules: &str) -> AppConfig {
+        let rules = if rules.is_empty() { String::new() } else { format!(", {rules}") };
+        config(&format!(r#"{{
+            "firefox_path": "/opt/firefox",
+            "use_active_profile": false,
+            "default_profile": "Personal"{rules}
+        }}"#))
+    }
+
+    /// A fixed set of running Firefox processes, `frontmost` owning the frontmost window.
+    #[derive(Default)]
+    struct FixedProcesses {
+        processes: Vec<FirefoxInfo>,
+        frontmost: Option<FirefoxInfo>,
+    }
+
+    impl ProcessSource for FixedProcesses {
+        fn firefox_processes(&self) -> Vec<FirefoxInfo> {
+            self.processes.clone()
+        }
+
+        fn frontmost_firefox(&self) -> Option<FirefoxInfo> {
+            self.frontmost.clone()
+        }
+    }
+
+    fn launched_commands(args: &[&str], config: &AppConfig) -> Vec<Vec<String>> {
+        launched_commands_with(args, config, &FixedProcesses::default())
+    }
+
+    fn launched_commands_with(args: &[&str], config: &AppConfig, processes: &FixedProcesses) -> Vec<Vec<String>> {
+        let launcher = RecordingLauncher::default();
+        open_links(urls(args), Some(config), &launcher, processes).unwrap();
+        launcher.commands.into_inner()
+    }
+
+    /// The profile `args` open in when `processes` are running, with the active profile detection on.
+    fn active_profile_commands(args: &[&str], processes: FixedProcesses) -> Vec<Vec<String>> {
+        let config = config(r#"{ "firefox_path": "/opt/firefox", "default_profile": "Personal" }"#);
+        launched_commands_with(args, &config, &processes)
+    }
+
+    /// Launches nothing, but isn't a dry run, so the history is recorded. Fails to launch
+    /// `failing_program`, if any.
+    #[derive(Default)]
+    struct SilentLauncher {
+        failing_program: Option<&'static str>,
+    }
+
+    impl Launcher for SilentLauncher {
+        fn launch(&self, command: &mut Command) -> std::io::Result<()> {
+            match self.failing_program {
+                Some(program) if command.get_program() == program => Err(std::io::ErrorKind::NotFound.into()),
+                _ => Ok(()),
+            }
+        }
+
+        fn is_dry_run(&self) -> bool {
+            false
+        }
+
+        fn is_default_browser(&self) -> bool {
+            false
+        }
+    }
+
+    /// Fails to launch with each of `failures` in turn, then launches nothing, counting the attempts.
+    struct FailingLauncher {
+        failures: std::cell::RefCell<std::vec::IntoIter<std::io::Error>>,
+        attempts: std::cell::Cell<usize>,
+    }
+
+    impl FailingLauncher {
+        fn new(failures: Vec<std::io::Error>) -> Self {
+            Self { failures: std::cell::RefCell::new(failures.into_iter()), attempts: std::cell::Cell::new(0) }
+        }
+    }
+
+    impl Launcher for FailingLauncher {
+        fn launch(&self, _: &mut Command) -> std::io::Result<()> {
+            self.attempts.set(self.attempts.get() + 1);
+            self.failures.borrow_mut().next().map_or(Ok(()), Err)
+        }
+    }
+
+    /// Launches Firefox through `launcher`, retrying up to `retries` times without waiting.
+    fn launch_with_retries(launcher: &FailingLauncher, retries: u32) -> std::io::Result<()> {
+        with_spawn_retries(retries, Duration::ZERO, || launcher.launch(&mut Command::new("/opt/firefox")))
+    }
+
+    fn route(profile_name: Option<&str>, action: RuleAction) -> Route {
+        Route {
+            profile_name: profile_name.map(str::to_owned),
+            profile_pool: None,
+            action,
+        }
+    }
+
+    fn pool(profiles: &[&str], strategy: ProfileStrategy) -> ProfilePool {
+        ProfilePool { profiles: urls(profiles), strategy }
+    }
+
+    fn named_firefox(profile: &str) -> FirefoxInfo {
+        FirefoxInfo { path: "/running/firefox".to_owned(), profile: Some(ProfileRef::Named(profile.to_owned())), start_time: 0 }
+    }
+
+    fn running_firefox() -> FirefoxInfo {
+        FirefoxInfo { path: "/running/firefox".to_owned(), profile: None, start_time: 0 }
+    }
+
+    fn command_line(command: &Command) -> Vec<String> {
+        std::iter::once(command.get_program()).chain(command.get_args())
+            .map(|it| it.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    /// Profile rules
+
+    #[test]
+    fn resolve_route_first_matching_rule_wins() {
+        let config = config(r#"{"ignored_urls": [], "ignored_urls_regex": [], "profile_rules": [
+            {"pattern": "https://*.work.com/**", "profile": "Work"},
+            {"pattern": "https://**", "profile": "Personal"}
+        ]}"#);
+        assert_eq!(resolve_route("https://git.work.com/repo", Some(&config)), route(Some("Work"), RuleAction::Open));
+        assert_eq!(resolve_route("https://example.com", Some(&config)), route(Some("Personal"), RuleAction::Open));
+    }
+
+    #[test]
+    fn resolve_route_no_match() {
+        let config = config(r#"{"ignored_urls": [], "ignored_urls_regex": [], "profile_rules": [
+            {"pattern": "https://*.work.com/**", "profile": "Work"}
+        ]}"#);
+        assert_eq!(resolve_route("https://example.com", Some(&config)), Route::default());
+        assert_eq!(resolve_route("https://example.com", None), Route::default());
+    }
+
+    #[test]
+    fn resolve_route_private_window_keeps_profile() {
+        let config = config(r#"{"ignored_urls": [], "ignored_urls_regex": [], "profile_rules": [
+            {"pattern": "https://*.bank.com/**", "profile": "Personal", "action": "private_window"},
+            {"pattern": "https://*.shop.com/**", "action": "private_window"}
+        ]}"#);
+        assert_eq!(resolve_route("https://www.bank.com/login", Some(&config)), route(Some("Personal"), RuleAction::PrivateWindow));
+        assert_eq!(resolve_route("https://www.shop.com/cart", Some(&config)), route(None, RuleAction::PrivateWindow));
+    }
+
+    #[test]
+    fn resolve_route_container() {
+        let config = config(r#"{"ignored_urls": [], "ignored_urls_regex": [], "profile_rules": [
+            {"pattern": "https://*.work.com/**", "action": {"container": "Work"}}
+        ]}"#);
+        assert_eq!(resolve_route("https://git.work.com/a", Some(&config)), route(None, RuleAction::Container(Container::named("Work"))));
+    }
+
+    #[test]
+    fn container_url_encodes_inner_url() {
+        assert_eq!(
+            container_url("Work", "https://example.com/search?q=a&b=c"),
+            "ext+container:name=Work&url=https%3A%2F%2Fexample.com%2Fsearch%3Fq%3Da%26b%3Dc",
+        );
+    }
+
+    #[test]
+    fn container_url_encodes_name() {
+        assert_eq!(
+            container_url("Work & Play", "https://example.com"),
+            "ext+container:name=Work%20%26%20Play&url=https%3A%2F%2Fexample.com",
+        );
+    }
+
+    #[test]
+    fn reader_url_encodes_inner_url() {
+        assert_eq!(
+            reader_url("https://docs.rs/serde/latest/serde/?search=a b&x=1#top"),
+            "about:reader?url=https%3A%2F%2Fdocs.rs%2Fserde%2Flatest%2Fserde%2F%3Fsearch%3Da%20b%26x%3D1%23top",
+        );
+    }
+
+    #[test]
+    fn open_links_opens_reader_mode_rules_in_their_profile() {
+        let config = launch_config(r#""profile_rules": [
+            { "pattern": "https://docs.rs/**", "profile": "Work", "action": "reader_mode" },
+            { "pattern": "https://*.python.org/**", "action": "reader_mode" }
+        ]"#);
+        let commands = launched_commands(&["https://docs.rs/serde?q=1", "https://docs.python.org/3/", "https://example.com/"], &config);
+        assert_eq!(commands, vec![
+            urls(&["/opt/firefox", "-P", "Work", "-url", "about:reader?url=https%3A%2F%2Fdocs.rs%2Fserde%3Fq%3D1"]),
+            urls(&["/opt/firefox", "-P", "Personal", "-url", "about:reader?url=https%3A%2F%2Fdocs.python.org%2F3%2F"]),
+            urls(&["/opt/firefox", "-P", "Personal", "-url", "https://example.com/"]),
+        ]);
+        assert!(explain_match("https://docs.rs/serde", &config).to_string().contains("opened in profile 'Work' in reader mode due to profile_rules[0]"));
+    }
+
+    #[test]
+    fn open_links_reuses_named_container() {
+        let config = launch_config(r#""profile_rules": [{ "pattern": "https://git.work.com/**", "action": { "container": "Work" } }]"#);
+        let commands = launched_commands(&["https://git.work.com/a?q=1&r=2"], &config);
+        assert_eq!(commands, vec![urls(&[
+            "/opt/firefox", "-P", "Personal", "-url", "ext+container:name=Work&url=https%3A%2F%2Fgit.work.com%2Fa%3Fq%3D1%26r%3D2",
+        ])]);
+    }
+
+    #[test]
+    fn open_links_opens_ephemeral_container_per_launch() {
+        let config = launch_config(r#""profile_rules": [
+            { "pattern": "https://bank.com/**", "action": { "container": { "name": "Bank & Co", "mode": "ephemeral" } } }
+        ]"#);
+        let container_names = || {
+            let commands = launched_commands(&["https://bank.com/a?x=1&y=2", "https://bank.com/b"], &config);
+            let [command] = commands.as_slice() else {
+                panic!("Expected a single command, got {commands:?}");
+            };
+            let names: Vec<String> = [(4, "https%3A%2F%2Fbank.com%2Fa%3Fx%3D1%26y%3D2"), (6, "https%3A%2F%2Fbank.com%2Fb")]
+                .into_iter()
+                .map(|(index, encoded_url)| {
+                    let arg = command[index].strip_prefix("ext+container:name=Bank%20%26%20Co-").unwrap_or_else(|| panic!("Unexpected URL: {}", command[index]));
+                    let (suffix, url) = arg.split_once('&').unwrap();
+                    assert_eq!(url, format!("url={encoded_url}"));
+                    assert!(suffix.chars().all(|it| it.is_ascii_digit() || it == '-'), "Unexpected name suffix: {suffix}");
+                    suffix.to_owned()
+                })
+                .collect();
+            assert_eq!(names[0], names[1], "URLs of a launch should share their container");
+            names[0].clone()
+        };
+        assert_ne!(container_names(), container_names());
+    }
+
+    #[test]
+    fn profile_rules_are_optional() {
+        let config = config(r#"{"ignored_urls": [], "ignored_urls_regex": []}"#);
+        assert!(config.profile_rules.is_empty());
+    }
+
+    #[test]
+    fn group_by_route_keeps_first_seen_order() {
+        let config = config(r#"{"ignored_urls": [], "ignored_urls_regex": [], "profile_rules": [
+            {"pattern": "https://*.work.com/**", "profile": "Work"}
+        ]}"#);
+        let groups = group_by_route(Some(&config), urls(&[
+            "https://example.com",
+            "https://git.work.com/a",
+            "https://other.com",
+            "https://wiki.work.com/b",
+        ]));
+        assert_eq!(groups, vec![
+            (Route::default(), urls(&["https://example.com", "https://other.com"])),
+            (route(Some("Work"), RuleAction::Open), urls(&["https://git.work.com/a", "https://wiki.work.com/b"])),
+        ]);
+    }
+
+    #[test]
+    fn group_by_route_batches_private_urls() {
+        let config = config(r#"{"ignored_urls": [], "ignored_urls_regex": [], "profile_rules": [
+            {"pattern": "https://*.work.com/**", "profile": "Work"},
+            {"pattern": "https://*.bank.com/**", "profile": "Work", "action": "private_window"}
+        ]}"#);
+        let groups = group_by_route(Some(&config), urls(&[
+            "https://www.bank.com/a",
+            "https://git.work.com/a",
+            "https://app.bank.com/b",
+        ]));
+        assert_eq!(groups, vec![
+            (route(Some("Work"), RuleAction::PrivateWindow), urls(&["https://www.bank.com/a", "https://app.bank.com/b"])),
+            (route(Some("Work"), RuleAction::Open), urls(&["https://git.work.com/a"])),
+        ]);
+    }
+
+    #[test]
+    fn add_to_group_merges_routes_with_the_same_profile() {
+        let work = || Some(ProfileRef::Named("Work".to_owned()));
+        let mut groups = Vec::new();
+        add_to_group(&mut groups, work(), RuleAction::Open, urls(&["https://a.com"]));
+        add_to_group(&mut groups, None, RuleAction::Open, urls(&["https://b.com"]));
+        add_to_group(&mut groups, work(), RuleAction::PrivateWindow, urls(&["https://c.com"]));
+        add_to_group(&mut groups, work(), RuleAction::Open, urls(&["https://d.com"]));
+        assert_eq!(groups, vec![
+            LaunchGroup { profile: work(), action: RuleAction::Open, urls: urls(&["https://a.com", "https://d.com"]) },
+            LaunchGroup { profile: None, action: RuleAction::Open, urls: urls(&["https://b.com"]) },
+            LaunchGroup { profile: work(), action: RuleAction::PrivateWindow, urls: urls(&["https://c.com"]) },
+        ]);
+    }
+
+    #[test]
+    fn resolve_profile_prefers_route_then_active_then_default() {
+        let config = config(r#"{"default_profile": "Fallback"}"#);
+        let named = |name: &str| Some(ProfileRef::Named(name.to_owned()));
+        let active = named_firefox("Active");
+        assert_eq!(resolve_profile(named("Work"), Some(&active), Some(&config)), named("Work"));
+        assert_eq!(resolve_profile(None, Some(&active), Some(&config)), named("Active"));
+        assert_eq!(resolve_profile(None, None, None), None);
+
+        let profile = resolve_profile(None, None, Some(&config));
+        assert_eq!(profile, named("Fallback"));
+        let command = firefox_command(&urls(&["https://a.com"]), Path::new("firefox"), profile, &RuleAction::Open, OpenMode::Url);
+        let args: Vec<&OsStr> = command.get_args().collect();
+        assert_eq!(args, ["-P", "Fallback", "-url", "https://a.com"].map(OsStr::new));
+
+        assert_eq!(resolve_profile(None, Some(&running_firefox()), Some(&config)), named("Fallback"));
+    }
+
+    /// Rule priority
+
+    #[test]
+    fn higher_priority_profile_rule_wins_regardless_of_order() {
+        let config = config(r#"{"profile_rules": [
+            {"pattern": "https://**", "profile": "Personal"},
+            {"pattern": "https://*.work.com/**", "profile": "Work", "priority": 1}
+        ]}"#);
+        assert_eq!(resolve_route("https://git.work.com/a", Some(&config)), route(Some("Work"), RuleAction::Open));
+        assert_eq!(resolve_route("https://example.com", Some(&config)), route(Some("Personal"), RuleAction::Open));
+    }
+
+    #[test]
+    fn priority_decides_between_browser_and_profile_rules() {
+        let config = config(r#"{
+            "browser_rules": [{"pattern": "https://teams.com/**", "command": "msedge.exe"}],
+            "profile_rules": [
+                {"pattern": "https://teams.com/**", "profile": "Work"},
+                {"pattern": "https://teams.com/meet/**", "profile": "Meetings", "priority": 5}
+            ]
+        }"#);
+        assert!(resolve_browser_rule("https://teams.com/chat", Some(&config)).is_some());
+        assert_eq!(resolve_route("https://teams.com/chat", Some(&config)), Route::default());
+        assert!(resolve_browser_rule("https://teams.com/meet/1", Some(&config)).is_none());
+        assert_eq!(resolve_route("https://teams.com/meet/1", Some(&config)), route(Some("Meetings"), RuleAction::Open));
+    }
+
+    #[test]
+    fn positive_priority_route_overrides_ignore() {
+        let config = config(r#"{
+            "ignored_urls": ["https://*.corp.com/**"],
+            "profile_rules": [
+                {"pattern": "https://wiki.corp.com/**", "profile": "Work", "priority": 1},
+                {"pattern": "https://git.corp.com/**", "profile": "Work"}
+            ]
+        }"#);
+        let args = ["https://wiki.corp.com/a", "https://git.corp.com/a", "https://mail.corp.com"];
+        assert_eq!(filter_args(Some(&config), args).unwrap(), urls(&["https://wiki.corp.com/a"]));
+
+        let explanation = explain_match("https://wiki.corp.com/a", &config);
+        assert_eq!(explanation.overriding_rule, Some(MatchedRule::Profile(0)));
+        assert!(explanation.to_string().contains("Filtering overridden by profile_rules[0]"));
+        assert_eq!(resolve("https://git.corp.com/a", &config), Decision::Ignore);
+    }
+
+    #[test]
+    fn positive_priority_route_overrides_allowlist() {
+        let config = config(r#"{
+            "allowed_urls": ["https://*.work.com/**"],
+            "browser_rules": [{"pattern": "https://teams.com/**", "command": "msedge.exe", "priority": 1}]
+        }"#);
+        let decision = resolve("https://teams.com/chat", &config);
+        assert!(matches!(decision, Decision::OpenInBrowser { command, .. } if command == "msedge.exe"));
+        assert_eq!(resolve("https://example.com", &config), Decision::Ignore);
+    }
+
+    #[test]
+    fn regex_profile_rules_route_urls() {
+        let config = config(r#"{"profile_rules_regex": [
+            {"match": "^https://[a-z]+\\.work\\.com/(issues|pulls)/", "profile": "Work", "action": "private_window"}
+        ]}"#);
+        assert_eq!(resolve_route("https://git.work.com/issues/1", Some(&config)), route(Some("Work"), RuleAction::PrivateWindow));
+        assert_eq!(resolve_route("https://git.work.com/wiki", Some(&config)), Route::default());
+
+        let explanation = explain_match("https://git.work.com/pulls/2", &config);
+        assert_eq!(explanation.profile_regex_rule, Some(0));
+        assert!(explanation.to_string().contains("due to profile_rules_regex[0]"));
+    }
+
+    #[test]
+    fn regex_profile_rules_follow_glob_rules_unless_prioritized() {
+        let config = config(r#"{
+            "profile_rules": [{"pattern": "https://*.work.com/**", "profile": "Work"}],
+            "profile_rules_regex": [
+                {"match": "^https://git\\.", "profile": "Git"},
+                {"match": "/admin/", "profile": "Admin", "priority": 1}
+            ]
+        }"#);
+        assert_eq!(resolve_route("https://git.work.com/a", Some(&config)), route(Some("Work"), RuleAction::Open));
+        assert_eq!(resolve_route("https://git.example.com/a", Some(&config)), route(Some("Git"), RuleAction::Open));
+        assert_eq!(resolve_route("https://git.work.com/admin/", Some(&config)), route(Some("Admin"), RuleAction::Open));
+    }
+
+    /// Browser rules
+
+    #[test]
+    fn resolve_browser_rule_matches() {
+        let config = config(r#"{"ignored_urls": [], "ignored_urls_regex": [], "browser_rules": [
+            {"pattern": "https://teams.microsoft.com/**", "command": "msedge.exe"}
+        ]}"#);
+        let rule = resolve_browser_rule("https://teams.microsoft.com/l/meeting", Some(&config)).unwrap();
+        assert_eq!(rule.command, "msedge.exe");
+        assert!(resolve_browser_rule("https://example.com", Some(&config)).is_none());
+    }
+
+    #[test]
+    fn browser_args_appends_url_without_placeholder() {
+        let config = config(r#"{"ignored_urls": [], "ignored_urls_regex": [], "browser_rules": [
+            {"pattern": "https://**", "command": "msedge.exe", "args": ["--new-window"]}
+        ]}"#);
+        assert_eq!(
+            browser_args("https://example.com", &config.browser_rules[0]),
+            urls(&["--new-window", "https://example.com"]),
+        );
+    }
+
+    #[test]
+    fn browser_args_substitutes_placeholder() {
+        let config = config(r#"{"ignored_urls": [], "ignored_urls_regex": [], "browser_rules": [
+            {"pattern": "https://**", "command": "chrome.exe", "args": ["--app={url}", "--incognito"]}
+        ]}"#);
+        assert_eq!(
+            browser_args("https://example.com", &config.browser_rules[0]),
+            urls(&["--app=https://example.com", "--incognito"]),
+        );
+    }
+
+    /// URL filtering
+
+    #[test]
+    fn filter_args_removes_ignored_urls() {
+        let config = config(r#"{"ignored_urls": ["https://docs.com/**"], "ignored_urls_regex": []}"#);
+        let filtered = filter_args(Some(&config), ["https://docs.com/a", "https://example.com"]).unwrap();
+        assert_eq!(filtered, urls(&["https://example.com"]));
+    }
+
+    #[test]
+    fn filter_args_negated_glob_un_ignores() {
+        let config = config(r#"{"ignored_urls": ["https://docs.com/**", "!https://docs.com/public/**"], "ignored_urls_regex": []}"#);
+        let filtered = filter_args(Some(&config), ["https://docs.com/private/a", "https://docs.com/public/a"]).unwrap();
+        assert_eq!(filtered, urls(&["https://docs.com/public/a"]));
+    }
+
+    #[test]
+    fn filter_args_negated_glob_only_affects_prior_rules() {
+        let config = config(r#"{"ignored_urls": ["!https://docs.com/public/**", "https://docs.com/**"], "ignored_urls_regex": []}"#);
+        let filtered = filter_args(Some(&config), ["https://docs.com/public/a"]).unwrap();
+        assert!(filtered.is_empty());
+    }
+
+    #[test]
+    fn filter_args_negated_glob_alone_ignores_nothing() {
+        let config = config(r#"{"ignored_urls": ["!https://docs.com/**"], "ignored_urls_regex": []}"#);
+        let filtered = filter_args(Some(&config), ["https://docs.com/a", "https://example.com"]).unwrap();
+        assert_eq!(filtered, urls(&["https://docs.com/a", "https://example.com"]));
+    }
+
+    #[test]
+    fn filter_args_negated_glob_does_not_override_regex() {
+        let config = config(r#"{"ignored_urls": ["https://docs.com/**", "!https://docs.com/public/**"], "ignored_urls_regex": ["public"]}"#);
+        let filtered = filter_args(Some(&config), ["https://docs.com/public/a"]).unwrap();
+        assert!(filtered.is_empty());
+    }
+
+    #[test]
+    fn filter_args_keeps_only_allowed_urls() {
+        let config = config(r#"{"ignored_urls": [], "ignored_urls_regex": [], "allowed_urls": ["https://*.work.com/**"]}"#);
+        let filtered = filter_args(Some(&config), ["https://git.work.com/a", "https://example.com"]).unwrap();
+        assert_eq!(filtered, urls(&["https://git.work.com/a"]));
+    }
+
+    #[test]
+    fn filter_args_empty_allowlist_keeps_everything() {
+        let config = config(r#"{"ignored_urls": [], "ignored_urls_regex": [], "allowed_urls": []}"#);
+        let filtered = filter_args(Some(&config), ["https://git.work.com/a", "https://example.com"]).unwrap();
+        assert_eq!(filtered, urls(&["https://git.work.com/a", "https://example.com"]));
+    }
+
+    #[test]
+    fn filter_args_ignore_takes_precedence_over_allow() {
+        let config = config(r#"{"ignored_urls": ["https://git.work.com/**"], "ignored_urls_regex": [], "allowed_urls": ["https://*.work.com/**"]}"#);
+        let filtered = filter_args(Some(&config), ["https://git.work.com/a", "https://wiki.work.com/a"]).unwrap();
+        assert_eq!(filtered, urls(&["https://wiki.work.com/a"]));
+    }
+
+    #[test]
+    fn filter_args_removes_duplicate_urls() {
+        let config = config(r#"{"ignored_urls": [], "ignored_urls_regex": []}"#);
+        let filtered = filter_args(Some(&config), ["https://a.com", "https://b.com", "https://a.com"]).unwrap();
+        assert_eq!(filtered, urls(&["https://a.com", "https://b.com"]));
+        assert_eq!(filter_args(None, ["https://a.com", "https://a.com"]).unwrap(), urls(&["https://a.com"]));
+    }
+
+    #[test]
+    fn filter_args_keeps_duplicates_when_disabled() {
+        let config = config(r#"{"ignored_urls": [], "ignored_urls_regex": [], "dedupe_urls": false}"#);
+        let args = ["https://a.com", "https://b.com", "https://a.com"];
+        assert_eq!(filter_args(Some(&config), args).unwrap(), urls(&args));
+    }
+
+    #[test]
+    fn filter_args_drops_urls_outside_the_length_bounds() {
+        // "https://a.com/" is 14 characters long
+        let config = config(r#"{"min_url_len": 14, "max_url_len": 16}"#);
+        let args = ["https://a.com", "https://a.com/", "https://a.com/bc", "https://a.com/bcd"];
+        assert_eq!(filter_args(Some(&config), args).unwrap(), urls(&["https://a.com/", "https://a.com/bc"]));
+    }
+
+    #[test]
+    fn filter_args_length_bounds_are_optional() {
+        let long_url = format!("https://a.com/{}", "a".repeat(10_000));
+        let args = ["https://a.com", long_url.as_str()];
+        let config_min = config(r#"{"min_url_len": 14}"#);
+        assert_eq!(filter_args(Some(&config_min), args).unwrap(), urls(&args[1..]));
+        let config_max = config(r#"{"max_url_len": 13}"#);
+        assert_eq!(filter_args(Some(&config_max), args).unwrap(), urls(&args[..1]));
+        assert_eq!(filter_args(Some(&config("{}")), args).unwrap(), urls(&args));
+    }
+
+    #[test]
+    fn filter_args_length_is_counted_in_characters() {
+        let config = config(r#"{"max_url_len": 16}"#);
+        assert_eq!(filter_args(Some(&config), ["https://é.com/éé"]).unwrap(), urls(&["https://é.com/éé"]));
+    }
+
+    #[test]
+    fn filter_args_length_bounds_apply_before_priority_rules() {
+        let config = config(r#"{
+            "max_url_len": 20,
+            "ignored_urls": ["https://**"],
+            "profile_rules": [{"pattern": "https://a.com/**", "profile": "Work", "priority": 1}]
+        }"#);
+        let filtered = filter_args(Some(&config), ["https://a.com/b", "https://a.com/bcdefghij"]).unwrap();
+        assert_eq!(filtered, urls(&["https://a.com/b"]));
+    }
+
+    #[test]
+    fn filter_args_anchored_regex_only_ignores_whole_urls() {
+        let args = ["https://example.com/a", "https://notexample.com/a"];
+        let unanchored = config(r#"{"ignored_urls_regex": ["(https://)?example\\.com/.*"]}"#);
+        assert_eq!(filter_args(Some(&unanchored), args).unwrap(), urls(&[]));
+        let anchored = config(r#"{"ignored_urls_regex": [{"pattern": "(https://)?example\\.com/.*", "anchored": true}]}"#);
+        assert_eq!(filter_args(Some(&anchored), args).unwrap(), urls(&["https://notexample.com/a"]));
+    }
+
+    #[test]
+    fn filter_args_keeps_local_file_paths() {
+        let config = config(r#"{"ignored_urls": ["https://*.tracking.com/**"], "ignored_urls_regex": []}"#);
+        let paths = [r"C:\Users\me\Documents\report.pdf", "/home/me/report.pdf"];
+        assert_eq!(filter_args(Some(&config), paths).unwrap(), urls(&paths));
+    }
+
+    #[test]
+    fn special_characters_survive_filtering_and_launch() {
+        let config = config(r#"{"ignored_urls": [], "ignored_urls_regex": [], "strip_query_params": ["utm_source"]}"#);
+        let url = r#"https://example.com/a b/100%25?q="x y"&r=1%2B1&utm_source=z"#;
+        let filtered = filter_args(Some(&config), [url]).unwrap();
+        assert_eq!(filtered, urls(&[url]));
+
+        let cleaned = clean_query(&apply_rewrites(&filtered[0], Some(&config)), &config.strip_query_params);
+        assert_eq!(cleaned, r#"https://example.com/a b/100%25?q="x y"&r=1%2B1"#);
+        let command = firefox_command(std::slice::from_ref(&cleaned), Path::new("firefox"), None, &RuleAction::Open, OpenMode::Url);
+        let args: Vec<&OsStr> = command.get_args().collect();
+        assert_eq!(args, [OsStr::new("-url"), OsStr::new(&cleaned)]);
+    }
+
+    #[test]
+    fn open_links_passes_encoded_spaces_unchanged() {
+        let url = "https://example.com/my%20page?q=a%20b&c=%22d%22";
+        let commands = launched_commands(&[url], &launch_config(""));
+        assert_eq!(commands, vec![urls(&["/opt/firefox", "-P", "Personal", "-url", url])]);
+    }
+
+    /// Local file paths
+
+    #[test]
+    fn file_path_to_url_converts_windows_path_with_spaces() {
+        let url = file_path_to_url(r"C:\Users\me\My Documents\annual report.pdf".to_owned());
+        assert_eq!(url, "file:///C:/Users/me/My%20Documents/annual%20report.pdf");
+    }
+
+    #[test]
+    fn file_path_to_url_accepts_forward_slashes_after_drive() {
+        assert_eq!(file_path_to_url("d:/pages/index.html".to_owned()), "file:///d:/pages/index.html");
+    }
+
+    #[test]
+    fn file_path_to_url_converts_unc_path() {
+        let url = file_path_to_url(r"\\fileserver\shared docs\page.html".to_owned());
+        assert_eq!(url, "file://fileserver/shared%20docs/page.html");
+    }
+
+    #[test]
+    fn file_path_to_url_converts_unix_path() {
+        let url = file_path_to_url("/home/me/a #1 & b.html".to_owned());
+        assert_eq!(url, "file:///home/me/a%20%231%20%26%20b.html");
+    }
+
+    #[test]
+    fn file_path_to_url_keeps_urls() {
+        for arg in ["https://example.com/C:\\a", "file:///C:/a.html", "mailto:me@example.com", "example.com", "//example.com"] {
+            assert_eq!(file_path_to_url(arg.to_owned()), arg);
+        }
+    }
+
+    #[test]
+    fn local_paths_only_match_file_globs() {
+        let config = config(r#"{"ignored_urls": ["https://**", "file:///C:/Temp/**"], "ignored_urls_regex": []}"#);
+        let paths = [r"C:\Users\me\My Documents\report.pdf", r"C:\Temp\page one.html"];
+        let urls: Vec<String> = paths.into_iter().map(|it| file_path_to_url(it.to_owned())).collect();
+        assert_eq!(filter_args(Some(&config), urls).unwrap(), vec!["file:///C:/Users/me/My%20Documents/report.pdf"]);
+    }
+
+    #[test]
+    fn local_paths_are_routed_by_file_globs() {
+        let config = config(r#"{"profile_rules": [{"pattern": "file:///C:/Work/**", "profile": "Work"}]}"#);
+        let url = file_path_to_url(r"C:\Work\Q3 plan.html".to_owned());
+        assert_eq!(resolve_route(&url, Some(&config)).profile_name.as_deref(), Some("Work"));
+    }
+
+    /// Scheme-less input
+
+    #[test]
+    fn normalize_input_adds_https_to_bare_hosts() {
+        assert_eq!(normalize_input("www.example.com"), "https://www.example.com");
+        assert_eq!(normalize_input("example.com/a b?q=1#top"), "https://example.com/a b?q=1#top");
+        assert_eq!(normalize_input("localhost:8080/app"), "https://localhost:8080/app");
+        assert_eq!(normalize_input("münchen.de"), "https://münchen.de");
+    }
+
+    #[test]
+    fn normalize_input_keeps_other_schemes() {
+        for input in ["about:preferences", "mailto:foo@bar.com", "javascript:void(0)", "https://example.com", "ftp://files.com"] {
+            assert_eq!(normalize_input(input), input);
+        }
+        assert_eq!(normalize_input("not a url"), "not a url");
+        assert_eq!(normalize_input("localhost"), "https://localhost");
+        assert_eq!(normalize_input("/home/me/page.html"), "file:///home/me/page.html");
+    }
+
+    #[test]
+    fn bare_hosts_are_filtered_and_routed_as_https() {
+        let config = config(r#"{
+            "ignored_urls": ["https://*.tracking.com/**"],
+            "profile_rules": [{"pattern": "https://*.work.com/**", "profile": "Work"}]
+        }"#);
+        assert_eq!(resolve("ads.tracking.com/pixel", &config), Decision::Ignore);
+        assert_eq!(resolve("git.work.com/repo", &config), Decision::OpenInFirefox {
+            url: "https://git.work.com/repo".to_owned(),
+            route: route(Some("Work"), RuleAction::Open),
+        });
+    }
+
+    /// Passthrough schemes
+
+    #[test]
+    fn non_http_schemes_pass_through_by_default() {
+        assert!(is_passthrough("mailto:foo@bar", None));
+        assert!(is_passthrough("about:preferences", None));
+        assert!(is_passthrough("ext+container:name=Work&url=x", None));
+        assert!(!is_passthrough("https://example.com", None));
+        assert!(!is_passthrough("HTTP://example.com", None));
+        assert!(!is_passthrough("file:///C:/page.html", None));
+        assert!(!is_passthrough("not a url", None));
+    }
+
+    #[test]
+    fn passthrough_schemes_can_be_configured() {
+        let config = config(r#"{"passthrough_schemes": ["about", "file"]}"#);
+        assert!(is_passthrough("about:config", Some(&config)));
+        assert!(is_passthrough("file:///home/me/page.html", Some(&config)));
+        assert!(!is_passthrough("mailto:foo@bar", Some(&config)));
+    }
+
+    #[test]
+    fn mailto_is_not_filtered() {
+        let config = config(r#"{"ignored_urls": ["https://**"], "allowed_urls": ["https://work.com/**"]}"#);
+        let decision = resolve("mailto:foo@bar", &config);
+        let expected_args: Vec<String> = DEFAULT_BROWSER_OPENER[1..].iter().copied().chain(["mailto:foo@bar"]).map(str::to_owned).collect();
+        assert_eq!(decision, Decision::OpenInBrowser {
+            url: "mailto:foo@bar".to_owned(),
+            command: DEFAULT_BROWSER_OPENER[0].to_owned(),
+            args: expected_args,
+        });
+        assert_eq!(resolve("about:preferences", &config), Decision::OpenInFirefox {
+            url: "about:preferences".to_owned(),
+            route: Route::default(),
+        });
+        assert!(explain_match("about:preferences", &config).to_string().contains("its scheme isn't routed"));
+    }
+
+    /// Scheme rules
+
+    #[test]
+    fn scheme_rules_decide_by_scheme() {
+        let config = config(r#"{"scheme_rules": [
+            {"scheme": "ftp", "action": "default_browser"},
+            {"scheme": "https", "action": {"profile": "Work"}},
+            {"scheme": "http", "action": "ignore"}
+        ]}"#);
+        let expected_args: Vec<String> = DEFAULT_BROWSER_OPENER[1..].iter().copied().chain(["ftp://files.com/a"]).map(str::to_owned).collect();
+        assert_eq!(resolve("ftp://files.com/a", &config), Decision::OpenInBrowser {
+            url: "ftp://files.com/a".to_owned(),
+            command: DEFAULT_BROWSER_OPENER[0].to_owned(),
+            args: expected_args,
+        });
+        let work_route = Route { profile_name: Some("Work".to_owned()), ..Default::default() };
+        assert_eq!(resolve("HTTPS://example.com/", &config), Decision::OpenInFirefox {
+            url: "HTTPS://example.com/".to_owned(),
+            route: work_route.clone(),
+        });
+        assert_eq!(resolve("http://example.com/", &config), Decision::Ignore);
+        // Scheme-less input gets the https scheme first
+        assert_eq!(resolve("example.com", &config), Decision::OpenInFirefox {
+            url: "https://example.com".to_owned(),
+            route: work_route,
+        });
+    }
+
+    #[test]
+    fn scheme_rules_only_apply_when_no_other_rule_matches() {
+        let config = config(r#"{
+            "ignored_urls": ["https://ads.com/**"],
+            "profile_rules": [{"pattern": "https://personal.com/**", "profile": "Personal"}],
+            "browser_rules": [{"pattern": "https://teams.com/**", "command": "chrome"}],
+            "scheme_rules": [{"scheme": "https", "action": {"profile": "Work"}}]
+        }"#);
+        assert_eq!(resolve("https://ads.com/a", &config), Decision::Ignore);
+        assert!(matches!(resolve("https://teams.com/a", &config), Decision::OpenInBrowser { command, .. } if command == "chrome"));
+        assert_eq!(resolve_route("https://personal.com/a", Some(&config)).profile_name.as_deref(), Some("Personal"));
+        assert_eq!(resolve_route("https://other.com/a", Some(&config)).profile_name.as_deref(), Some("Work"));
+    }
+
+    #[test]
+    fn scheme_rules_override_passthrough() {
+        let config = config(r#"{"scheme_rules": [{"scheme": "ftp", "action": "ignore"}]}"#);
+        assert!(!is_passthrough("ftp://files.com/a", Some(&config)));
+        assert!(is_passthrough("about:config", Some(&config)));
+        assert_eq!(resolve("ftp://files.com/a", &config), Decision::Ignore);
+        assert_eq!(explain_match("ftp://files.com/a", &config).to_string().trim_end(), [
+            "  Opened as: ftp://files.com/a",
+            "  Result: not opened due to scheme_rules[0]",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn default_browser_scheme_rules_fall_back_to_firefox_when_routed_back() {
+        let default_browser = SchemeAction::DefaultBrowser;
+        assert_eq!(scheme_action("https://example.com/", Some(&default_browser), || true), None);
+        assert_eq!(scheme_action("https://example.com/", Some(&default_browser), || false), Some(&default_browser));
+        assert_eq!(scheme_action("ftp://files.com/a", Some(&default_browser), || true), Some(&default_browser));
+        assert_eq!(scheme_action("http://example.com/", Some(&SchemeAction::Ignore), || true), Some(&SchemeAction::Ignore));
+
+        let config = launch_config(r#""scheme_rules": [{"scheme": "https", "action": "default_browser"}]"#);
+        let launcher = RecordingLauncher { is_default_browser: true, ..Default::default() };
+        open_links(urls(&["https://example.com/"]), Some(&config), &launcher, &FixedProcesses::default()).unwrap();
+        assert_eq!(launcher.commands.into_inner(), vec![urls(&["/opt/firefox", "-P", "Personal", "-url", "https://example.com/"])]);
+    }
+
+    #[test]
+    fn open_links_follows_scheme_rules() {
+        let config = launch_config(r#""scheme_rules": [
+            {"scheme": "ftp", "action": "default_browser"},
+            {"scheme": "gopher", "action": "ignore"},
+            {"scheme": "https", "action": {"profile": "Work"}}
+        ]"#);
+        let commands = launched_commands(&["ftp://files.com/a", "gopher://old.com", "https://example.com/", "http://example.com/"], &config);
+        assert_eq!(commands, vec![
+            urls(&DEFAULT_BROWSER_OPENER).into_iter().chain(["ftp://files.com/a".to_owned()]).collect(),
+            urls(&["/opt/firefox", "-P", "Work", "-url", "https://example.com/"]),
+            urls(&["/opt/firefox", "-P", "Personal", "-url", "http://example.com/"]),
+        ]);
+    }
+
+    /// Profile detection
+
+    #[test]
+    fn profile_arg_named() {
+        assert_eq!(
+            get_profile_arg(&["firefox.exe", "-P", "Work"]),
+            Some(ProfileRef::Named("Work".to_owned())),
+        );
+    }
+
+    #[test]
+    fn profile_arg_path() {
+        assert_eq!(
+            get_profile_arg(&["firefox.exe", "-profile", r"C:\Profiles\abc.work", "-no-remote"]),
+            Some(ProfileRef::Path(PathBuf::from(r"C:\Profiles\abc.work"))),
+        );
+        assert_eq!(
+            get_profile_arg(&["firefox", "-profile", "/home/user/.mozilla/firefox/abc.work"]),
+            Some(ProfileRef::Path(PathBuf::from("/home/user/.mozilla/firefox/abc.work"))),
+        );
+    }
+
+    #[test]
+    fn profile_arg_path_passed_to_named_flag() {
+        assert_eq!(
+            get_profile_arg(&["firefox.exe", "-P", "Profiles/abc.work"]),
+            Some(ProfileRef::Path(PathBuf::from("Profiles/abc.work"))),
+        );
+    }
+
+    #[test]
+    fn profile_arg_missing() {
+        assert_eq!(get_profile_arg(&["firefox.exe", "-url", "https://example.com"]), None);
+        assert_eq!(get_profile_arg(&["firefox.exe", "-P"]), None);
+    }
+
+    /// Firefox process detection
+
+    #[test]
+    fn firefox_ordering_prefers_profiles_then_newest() {
+        let info = |profile: Option<&str>, path: &str, start_time: u64| FirefoxInfo {
+            path: path.to_owned(),
+            profile: profile.map(|it| ProfileRef::Named(it.to_owned())),
+            start_time,
+        };
+        let mut processes = vec![
+            info(None, "a", 300),
+            info(Some("Work"), "a", 100),
+            info(Some("Work"), "b", 200),
+            info(Some("Personal"), "a", 50),
+            info(Some("Work"), "a", 200),
+        ];
+        processes.sort();
+        assert_eq!(processes, [
+            info(Some("Personal"), "a", 50),
+            info(Some("Work"), "a", 200),
+            info(Some("Work"), "b", 200),
+            info(Some("Work"), "a", 100),
+            info(None, "a", 300),
+        ]);
+    }
+
+    #[test]
+    fn default_firefox_executables() {
+        let executables = firefox_executables(None);
+        assert!(is_firefox_executable("C:/Program Files/Firefox/firefox.exe", &executables));
+        assert!(is_firefox_executable("/usr/lib/firefox/firefox-bin", &executables));
+        assert!(is_firefox_executable("firefox-esr.exe", &executables));
+        assert!(!is_firefox_executable("chrome.exe", &executables));
+    }
+
+    #[test]
+    fn firefox_executables_case_insensitive() {
+        assert!(is_firefox_executable("FireFox.EXE", &firefox_executables(None)));
+    }
+
+    #[test]
+    fn firefox_executables_extended_by_config() {
+        let config = config(r#"{"ignored_urls": [], "ignored_urls_regex": [], "firefox_executables": ["librewolf.exe"]}"#);
+        let executables = firefox_executables(Some(&config));
+        assert!(is_firefox_executable("librewolf.exe", &executables));
+        assert!(is_firefox_executable("firefox.exe", &executables));
+    }
+
+    /// CLI
+
+    #[test]
+    fn open_in_profile_requires_profile() {
+        assert!(open_in_profile(&[], &DryRunLauncher).is_err());
+    }
+
+    #[test]
+    fn open_in_profile_ignores_profile_rules() {
+        let config = launch_config(r#""profile_rules": [{ "pattern": "https://example.com/**", "profile": "Personal" }]"#);
+        let launcher = RecordingLauncher::default();
+        open_urls_in_profile("Work", &urls(&["https://example.com"]), Some(&config), &launcher).unwrap();
+        assert_eq!(launcher.commands.into_inner(), vec![urls(&["/opt/firefox", "-P", "Work", "-url", "https://example.com"])]);
+    }
+
+    #[test]
+    fn take_flag_removes_flag() {
+        let mut args = urls(&["https://example.com", "--dry-run"]);
+        assert!(take_flag(&mut args, "--dry-run"));
+        assert_eq!(args, urls(&["https://example.com"]));
+    }
+
+    #[test]
+    fn take_flag_missing() {
+        let mut args = urls(&["https://example.com"]);
+        assert!(!take_flag(&mut args, "--dry-run"));
+        assert_eq!(args, urls(&["https://example.com"]));
+    }
+
+    #[test]
+    fn remove_files_skips_missing_files() {
+        let folder = test_folder("reset");
+        let (cache, usage) = (folder.join("FirefoxRouter.cache.json"), folder.join("FirefoxRouter.usage.json"));
+        std::fs::write(&cache, "{}").unwrap();
+        let files = [cache.clone(), usage];
+
+        assert_eq!(remove_files(&files).unwrap(), vec![&cache]);
+        assert!(!cache.exists());
+        assert!(remove_files(&files).unwrap().is_empty(), "Running it again should remove nothing");
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn format_command_quotes_args() {
+        let mut command = Command::new("firefox.exe");
+        command.arg("-P").arg("My Profile").arg("-url").arg("https://example.com");
+        assert_eq!(format_command(&command), r#""firefox.exe" "-P" "My Profile" "-url" "https://example.com""#);
+    }
+
+    /// Profile pools
+
+    #[test]
+    fn resolve_route_with_profile_pool() {
+        let config = config(r#"{"profile_rules": [
+            {"pattern": "https://research.com/**", "profiles": ["R1", "R2"], "strategy": "round_robin"},
+            {"pattern": "https://work.com/**", "profile": "Work", "profiles": ["R1"]}
+        ]}"#);
+        let route = resolve_route("https://research.com/a", Some(&config));
+        assert_eq!(route.profile_pool, Some(pool(&["R1", "R2"], ProfileStrategy::RoundRobin)));
+        assert_eq!(resolve_route("https://work.com/a", Some(&config)).profile_pool, None);
+    }
+
+    #[test]
+    fn choose_profile_least_loaded() {
+        let pool = pool(&["R1", "R2", "R3"], ProfileStrategy::LeastLoaded);
+        let running = [named_firefox("R1"), named_firefox("r2"), named_firefox("R1")];
+        assert_eq!(choose_profile(&pool, &running, &ProfileUsage::new()), ProfileRef::Named("R3".to_owned()));
+
+        let running = [named_firefox("R1"), named_firefox("R2"), named_firefox("R3"), named_firefox("R1")];
+        assert_eq!(choose_profile(&pool, &running, &ProfileUsage::new()), ProfileRef::Named("R2".to_owned()));
+    }
+
+    #[test]
+    fn choose_profile_breaks_ties_by_least_recent_use() {
+        let pool = pool(&["R1", "R2", "R3"], ProfileStrategy::LeastLoaded);
+        let usage = ProfileUsage::from([("R1".to_owned(), 300), ("R2".to_owned(), 100), ("R3".to_owned(), 200)]);
+        assert_eq!(choose_profile(&pool, &[], &usage), ProfileRef::Named("R2".to_owned()));
+
+        let usage = ProfileUsage::from([("R1".to_owned(), 300)]);
+        assert_eq!(choose_profile(&pool, &[], &usage), ProfileRef::Named("R2".to_owned()));
+    }
+
+    #[test]
+    fn choose_profile_round_robin_ignores_load() {
+        let pool = pool(&["R1", "R2"], ProfileStrategy::RoundRobin);
+        let running = [named_firefox("R1"), named_firefox("R1")];
+        let usage = ProfileUsage::from([("R2".to_owned(), 100)]);
+        assert_eq!(choose_profile(&pool, &running, &usage), ProfileRef::Named("R1".to_owned()));
+    }
+
+    /// Profile prompt
+
+    #[test]
+    fn distinct_profiles_skips_duplicates_and_unknown_profiles() {
+        let info = |profile: Option<&str>, path: &str| FirefoxInfo {
+            path: path.to_owned(),
+            profile: profile.map(|it| ProfileRef::Named(it.to_owned())),
+            start_time: 0,
+        };
+        let processes = [info(Some("Personal"), "a"), info(Some("Personal"), "b"), info(None, "c"), info(Some("Work"), "d")];
+        let candidates = distinct_profiles(&processes);
+        assert_eq!(candidates, [&processes[0], &processes[3]]);
+    }
+
+    #[test]
+    fn prompt_for_firefox_needs_several_profiles() {
+        let processes = [running_firefox(), running_firefox()];
+        assert_eq!(prompt_for_firefox(&processes, Duration::ZERO), None);
+        assert_eq!(prompt_for_firefox(&[], Duration::ZERO), None);
+    }
+
+    #[test]
+    fn run_with_timeout_returns_result() {
+        assert_eq!(run_with_timeout(Duration::from_secs(5), || 42), Some(42));
+    }
+
+    #[test]
+    fn run_with_timeout_gives_up() {
+        let result = run_with_timeout(Duration::from_millis(10), || std::thread::sleep(Duration::from_secs(5)));
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn poll_until_returns_first_value() {
+        let mut calls = 0;
+        let result = poll_until(Duration::from_secs(5), Duration::from_millis(1), || {
+            calls += 1;
+            (calls == 3).then_some(calls)
+        });
+        assert_eq!(result, Some(3));
+    }
+
+    #[test]
+    fn poll_until_gives_up() {
+        let mut calls = 0;
+        let result: Option<()> = poll_until(Duration::from_millis(20), Duration::from_millis(5), || {
+            calls += 1;
+            None
+        });
+        assert_eq!(result, None);
+        assert!(calls > 1);
+    }
+
+    /// Launching
+
+    #[test]
+    fn open_links_launches_one_firefox_per_profile() {
+        let config = launch_config(r#""profile_rules": [{ "pattern": "https://*.work.com/**", "profile": "Work" }]"#);
+        let commands = launched_commands(&["https://app.work.com/a", "https://example.com/", "https://docs.work.com/b"], &config);
+        assert_eq!(commands, vec![
+            urls(&["/opt/firefox", "-P", "Work", "-url", "https://app.work.com/a", "-url", "https://docs.work.com/b"]),
+            urls(&["/opt/firefox", "-P", "Personal", "-url", "https://example.com/"]),
+        ]);
+    }
+
+    #[test]
+    fn open_links_opens_temp_profile_rules_in_new_profile() {
+        let config = launch_config(r#""profile_rules": [{ "pattern": "https://test.com/**", "profile": "Work", "action": "temp_profile" }]"#);
+        let commands = launched_commands(&["https://test.com/a", "https://example.com/"], &config);
+        let [temp_command, default_command] = commands.as_slice() else {
+            panic!("Expected 2 commands, got {commands:?}");
+        };
+        assert_eq!(temp_command[..2], urls(&["/opt/firefox", "-profile"]));
+        assert!(Path::new(&temp_command[2]).starts_with(std::env::temp_dir()), "Unexpected profile: {}", temp_command[2]);
+        assert!(!Path::new(&temp_command[2]).exists());
+        assert_eq!(temp_command[3..], urls(&["-url", "https://test.com/a"]));
+        assert_eq!(*default_command, urls(&["/opt/firefox", "-P", "Personal", "-url", "https://example.com/"]));
+    }
+
+    #[test]
+    fn open_links_adds_extra_args_of_launch_rules() {
+        let config = launch_config(r#""profile_rules": [{
+            "pattern": "https://grafana.com/**",
+            "profile": "Dashboards",
+            "action": { "launch": { "extra_args": ["--kiosk", "-width", "1920"], "open_mode": "new_window" } }
+        }]"#);
+        let commands = launched_commands(&["https://grafana.com/d/1", "https://grafana.com/d/2"], &config);
+        assert_eq!(commands, vec![urls(&[
+            "/opt/firefox", "-P", "Dashboards", "--kiosk", "-width", "1920",
+            "-new-window", "https://grafana.com/d/1", "-new-window", "https://grafana.com/d/2",
+        ])]);
+    }
+
+    #[test]
+    fn launch_rule_keeps_config_open_mode_by_default() {
+        let action = RuleAction::Launch { extra_args: urls(&["-headless"]), open_mode: None };
+        let command = firefox_command(&urls(&["https://a.com"]), Path::new("firefox"), None, &action, OpenMode::NewTab);
+        assert_eq!(command_line(&command), ["firefox", "-headless", "-new-tab", "https://a.com"]);
+    }
+
+    #[test]
+    fn open_links_splits_long_url_lists() {
+        let long_urls: Vec<String> = (0..40).map(|it| format!("https://example.com/{it:02}/{}", "a".repeat(1000))).collect();
+        let args: Vec<&str> = long_urls.iter().map(String::as_str).collect();
+        let commands = launched_commands(&args, &launch_config(""));
+        assert_eq!(commands.len(), 2);
+        for command in &commands {
+            assert_eq!(command[..3], urls(&["/opt/firefox", "-P", "Personal"]));
+            assert!(command.iter().map(|it| it.len() + 3).sum::<usize>() < 32767);
+        }
+        let opened: Vec<&String> = commands.iter().flat_map(|it| it[3..].iter().skip(1).step_by(2)).collect();
+        assert_eq!(opened, long_urls.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn temp_profile_is_created() {
+        let path = make_temp_profile(false).unwrap();
+        assert!(path.is_dir() && path.starts_with(std::env::temp_dir()));
+        assert_ne!(make_temp_profile(true).unwrap(), path);
+        std::fs::remove_dir(path).unwrap();
+    }
+
+    #[test]
+    fn open_links_hands_browser_rules_to_their_command() {
+        let config = launch_config(r#""browser_rules": [{ "pattern": "https://meet.com/**", "command": "chromium", "args": ["--app={url}"] }]"#);
+        let commands = launched_commands(&["https://meet.com/call"], &config);
+        assert_eq!(commands, vec![urls(&["chromium", "--app=https://meet.com/call"])]);
+    }
+
+    #[test]
+    fn open_links_skips_ignored_urls() {
+        let config = launch_config(r#""ignored_urls": ["https://ads.com/**"]"#);
+        assert!(launched_commands(&["https://ads.com/pixel"], &config).is_empty());
+    }
+
+    #[test]
+    fn open_links_hands_mailto_to_the_system() {
+        let commands = launched_commands(&["mailto:bob@example.com"], &launch_config(r#""passthrough_schemes": ["mailto"]"#));
+        let [program, args @ ..] = DEFAULT_BROWSER_OPENER;
+        let expected: Vec<String> = std::iter::once(program).chain(args).chain(["mailto:bob@example.com"]).map(str::to_owned).collect();
+        assert_eq!(commands, vec![expected]);
+    }
+
+    /// Active profile
+
+    #[test]
+    fn active_profile_prefers_named_process() {
+        let started = |profile: Option<&str>, start_time: u64| FirefoxInfo {
+            path: "/running/firefox".to_owned(),
+            profile: profile.map(|it| ProfileRef::Named(it.to_owned())),
+            start_time,
+        };
+        let processes = FixedProcesses {
+            processes: vec![started(None, 30), started(Some("Work"), 10), started(Some("Work"), 20)],
+            frontmost: None,
+        };
+        let (firefox_processes, window_firefox) = find_running_firefox(&processes);
+        let active = pick_active_firefox(None, window_firefox.as_ref(), &firefox_processes);
+        assert_eq!(active, Some(&started(Some("Work"), 20)));
+    }
+
+    #[test]
+    fn active_profile_prefers_frontmost_window() {
+        let processes = FixedProcesses {
+            processes: vec![named_firefox("Work"), named_firefox("Personal")],
+            frontmost: Some(named_firefox("Personal")),
+        };
+        let commands = active_profile_commands(&["https://example.com/"], processes);
+        assert_eq!(commands, vec![urls(&["/running/firefox", "-P", "Personal", "-url", "https://example.com/"])]);
+    }
+
+    #[test]
+    fn active_profile_prefers_chosen_firefox() {
+        let (chosen, window) = (named_firefox("Chosen"), named_firefox("Window"));
+        let processes = [named_firefox("First")];
+        assert_eq!(pick_active_firefox(Some(&chosen), Some(&window), &processes), Some(&chosen));
+        assert_eq!(pick_active_firefox(None, Some(&window), &processes), Some(&window));
+        assert_eq!(pick_active_firefox(None, None, &processes), Some(&processes[0]));
+    }
+
+    #[test]
+    fn no_running_firefox_opens_default_profile() {
+        let commands = active_profile_commands(&["https://example.com/"], FixedProcesses::default());
+        assert_eq!(commands, vec![urls(&["/opt/firefox", "-P", "Personal", "-url", "https://example.com/"])]);
+    }
+
+    #[test]
+    fn unnamed_running_firefox_opens_default_profile() {
+        let processes = FixedProcesses { processes: vec![running_firefox()], frontmost: None };
+        let commands = active_profile_commands(&["https://example.com/"], processes);
+        assert_eq!(commands, vec![urls(&["/running/firefox", "-P", "Personal", "-url", "https://example.com/"])]);
+    }
+
+    /// URL chunking
+
+    #[test]
+    fn chunk_urls_keeps_urls_fitting_together() {
+        let urls = urls(&["https://a.com/1", "https://a.com/2", "https://a.com/3"]);
+        let len = urls[0].len() + URL_ARG_OVERHEAD;
+        assert_eq!(chunk_urls(urls.clone(), len * 3), vec![urls.clone()]);
+        assert_eq!(chunk_urls(urls.clone(), len * 2), vec![urls[..2].to_vec(), urls[2..].to_vec()]);
+        assert_eq!(chunk_urls(urls.clone(), len * 2 - 1), vec![urls[..1].to_vec(), urls[1..2].to_vec(), urls[2..].to_vec()]);
+        assert!(chunk_urls(Vec::new(), len).is_empty());
+    }
+
+    #[test]
+    fn chunk_urls_gives_too_long_urls_their_own_chunk() {
+        let long = format!("https://a.com/{}", "x".repeat(100));
+        let urls = vec!["https://a.com/1".to_owned(), long.clone(), "https://a.com/2".to_owned()];
+        assert_eq!(chunk_urls(urls, 60), vec![vec!["https://a.com/1".to_owned()], vec![long], vec!["https://a.com/2".to_owned()]]);
+    }
+
+    /// Spawn retries
+
+    #[test]
+    fn spawn_is_retried_on_transient_error() {
+        let launcher = FailingLauncher::new(vec![std::io::ErrorKind::ResourceBusy.into()]);
+        assert!(launch_with_retries(&launcher, 1).is_ok());
+        assert_eq!(launcher.attempts.get(), 2);
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn spawn_is_retried_on_sharing_violation() {
+        let launcher = FailingLauncher::new(vec![std::io::Error::from_raw_os_error(32)]);
+        assert!(launch_with_retries(&launcher, 1).is_ok());
+        assert_eq!(launcher.attempts.get(), 2);
+    }
+
+    #[test]
+    fn spawn_is_not_retried_when_not_found() {
+        let launcher = FailingLauncher::new(vec![std::io::ErrorKind::NotFound.into()]);
+        assert_eq!(launch_with_retries(&launcher, 3).unwrap_err().kind(), std::io::ErrorKind::NotFound);
+        assert_eq!(launcher.attempts.get(), 1);
+    }
+
+    #[test]
+    fn spawn_gives_up_after_retries() {
+        let launcher = FailingLauncher::new((0..5).map(|_| std::io::ErrorKind::Interrupted.into()).collect());
+        assert_eq!(launch_with_retries(&launcher, 2).unwrap_err().kind(), std::io::ErrorKind::Interrupted);
+        assert_eq!(launcher.attempts.get(), 3);
+    }
+
+    /// Route notification
+
+    #[test]
+    fn route_notification_skips_default_profile() {
+        let default = FirefoxProfile { name: "default".to_owned(), path: PathBuf::from("/profiles/xyz.default") };
+        let profiles = [ProfileRef::Named("default".to_owned()), ProfileRef::Path(PathBuf::from("/profiles/xyz.default"))];
+        assert_eq!(route_notification(&profiles, Some(&default)), None);
+        assert_eq!(route_notification(&[], Some(&default)), None);
+    }
+
+    #[test]
+    fn route_notification_lists_other_profiles_once() {
+        let default = FirefoxProfile { name: "default".to_owned(), path: PathBuf::from("/profiles/xyz.default") };
+        let work = ProfileRef::Named("Work".to_owned());
+        assert_eq!(route_notification(std::slice::from_ref(&work), Some(&default)).as_deref(), Some("Opened in profile: Work"));
+
+        let profiles = [work.clone(), ProfileRef::Named("default".to_owned()), ProfileRef::Path(PathBuf::from("/profiles/abc.shopping")), work];
+        assert_eq!(route_notification(&profiles, None).as_deref(), Some("Opened in profiles: Work, default, abc.shopping"));
+    }
+
+    /// Library API
+
+    #[test]
+    fn resolve_ignored_url() {
+        let config = config(r#"{"ignored_urls": ["https://*.tracking.com/**"], "ignored_urls_regex": []}"#);
+        assert_eq!(resolve("https://ads.tracking.com/a", &config), Decision::Ignore);
+    }
+
+    #[test]
+    fn resolve_url_to_profile() {
+        let config = config(r#"{
+            "rewrite_rules": [{"match": "^http://", "replace": "https://"}],
+            "profile_rules": [{"pattern": "https://*.work.com/**", "profile": "Work", "action": "private_window"}]
+        }"#);
+        assert_eq!(resolve("http://git.work.com/repo", &config), Decision::OpenInFirefox {
+            url: "https://git.work.com/repo".to_owned(),
+            route: route(Some("Work"), RuleAction::PrivateWindow),
+        });
+        assert_eq!(resolve("https://example.com", &config), Decision::OpenInFirefox {
+            url: "https://example.com".to_owned(),
+            route: Route::default(),
+        });
+    }
+
+    #[test]
+    fn resolve_url_to_browser() {
+        let config = config(r#"{"browser_rules": [{"pattern": "https://meet.example.com/**", "command": "chrome", "args": ["--app={url}"]}]}"#);
+        assert_eq!(resolve("https://meet.example.com/abc", &config), Decision::OpenInBrowser {
+            url: "https://meet.example.com/abc".to_owned(),
+            command: "chrome".to_owned(),
+            args: urls(&["--app=https://meet.example.com/abc"]),
+        });
+    }
+
+    #[test]
+    fn resolve_local_path() {
+        let config = config(r#"{"profile_rules": [{"pattern": "file:///C:/Work/**", "profile": "Work"}]}"#);
+        let decision = resolve(r"C:\Work\notes.html", &config);
+        assert_eq!(decision, Decision::OpenInFirefox {
+            url: "file:///C:/Work/notes.html".to_owned(),
+            route: route(Some("Work"), RuleAction::Open),
+        });
+    }
+
+    /// Profile query
+
+    #[test]
+    fn profile_for_prints_a_single_token() {
+        let config = config(r#"{
+            "ignored_urls": ["https://ads.com/**"],
+            "profile_rules": [
+                {"pattern": "https://work.com/**", "profile": "Work"},
+                {"pattern": "https://research.com/**", "profiles": ["Research1", "Research2"]},
+                {"pattern": "https://test.com/**", "action": "temp_profile"}
+            ],
+            "browser_rules": [{"pattern": "https://teams.com/**", "command": "chrome"}]
+        }"#);
+        assert_eq!(profile_for("https://work.com/a", Some(&config)), "Work");
+        assert_eq!(profile_for("work.com/a", Some(&config)), "Work");
+        assert_eq!(profile_for("https://example.com", Some(&config)), "<default>");
+        assert_eq!(profile_for("https://ads.com/a", Some(&config)), "<ignored>");
+        assert_eq!(profile_for("https://teams.com/a", Some(&config)), "<browser>");
+        assert_eq!(profile_for("https://research.com/a", Some(&config)), "Research1,Research2");
+        assert_eq!(profile_for("https://test.com/a", Some(&config)), "<temp>");
+        assert_eq!(profile_for("https://work.com/a", None), "<default>");
+    }
+
+    /// History
+
+    #[test]
+    fn history_records_what_each_url_was_launched_with() {
+        let folder = test_folder("router-history-launches");
+        let path = folder.join("history.jsonl");
+        let config = config(&format!(r#"{{
+            "firefox_path": "/opt/firefox",
+            "history_file": {},
+            "ignored_urls": ["https://ads.com/**"],
+            "profile_rules": [
+                {{"pattern": "https://work.com/**", "profile": "Work"}},
+                {{"pattern": "https://test.com/**", "action": "temp_profile"}}
+            ],
+            "browser_rules": [{{"pattern": "https://teams.com/**", "command": "chrome"}}]
+        }}"#, serde_json::to_string(&path).unwrap()));
+        let processes = FixedProcesses { processes: vec![named_firefox("Personal")], frontmost: None };
+        let args = urls(&["https://ads.com/a", "https://teams.com/a", "https://work.com/a", "https://test.com/a", "https://example.com"]);
+        open_links(args, Some(&config), &SilentLauncher::default(), &processes).unwrap();
+
+        let entries = history::read_last(&path, 10).unwrap();
+        let summary: Vec<_> = entries.iter()
+            .map(|it| (it.url.as_str(), it.outcome, it.profile.as_deref(), it.rule.as_deref()))
+            .collect();
+        let temp_profile = entries[3].profile.clone().unwrap();
+        assert!(temp_profile.contains("FirefoxRouter-profile-"), "{temp_profile}");
+        assert_eq!(summary, [
+            ("https://ads.com/a", HistoryOutcome::Ignored, None, Some("ignored_urls[0]")),
+            ("https://teams.com/a", HistoryOutcome::Browser, None, Some("browser_rules[0]")),
+            ("https://work.com/a", HistoryOutcome::Firefox, Some("Work"), Some("profile_rules[0]")),
+            ("https://test.com/a", HistoryOutcome::Firefox, Some(temp_profile.as_str()), Some("profile_rules[1]")),
+            ("https://example.com", HistoryOutcome::Firefox, Some("Personal"), None),
+        ]);
+        std::fs::remove_dir_all(temp_profile).unwrap();
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn history_records_the_urls_that_failed_to_open() {
+        let folder = test_folder("router-history-failures");
+        let path = folder.join("history.jsonl");
+        let config = config(&format!(r#"{{
+            "firefox_path": "/opt/firefox",
+            "history_file": {},
+            "spawn_retries": 0,
+            "browser_rules": [{{"pattern": "https://teams.com/**", "command": "chrome"}}]
+        }}"#, serde_json::to_string(&path).unwrap()));
+        let processes = FixedProcesses { processes: vec![named_firefox("Personal")], frontmost: None };
+        let launcher = SilentLauncher { failing_program: Some("/running/firefox") };
+        let args = urls(&["https://teams.com/a", "https://example.com"]);
+        assert!(open_links(args, Some(&config), &launcher, &processes).is_err());
+
+        let entries = history::read_last(&path, 10).unwrap();
+        let summary: Vec<_> = entries.iter().map(|it| (it.url.as_str(), it.outcome)).collect();
+        assert_eq!(summary, [("https://teams.com/a", HistoryOutcome::Browser), ("https://example.com", HistoryOutcome::Failed)]);
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn history_is_only_recorded_when_configured() {
+        let folder = test_folder("router-history");
+        let path = folder.join("history.jsonl");
+        let args = || urls(&["https://example.com"]);
+        let processes = FixedProcesses { processes: vec![named_firefox("Personal")], frontmost: None };
+        let config = config(&format!(r#"{{"firefox_path": "/opt/firefox", "history_file": {}}}"#, serde_json::to_string(&path).unwrap()));
+        open_links(args(), Some(&launch_config("")), &SilentLauncher::default(), &processes).unwrap();
+        open_links(args(), Some(&config), &RecordingLauncher::default(), &processes).unwrap();
+        assert!(!path.exists());
+
+        open_links(args(), Some(&config), &SilentLauncher::default(), &processes).unwrap();
+        let entries = history::read_last(&path, 10).unwrap();
+        assert_eq!(entries.iter().map(|it| it.url.as_str()).collect::<Vec<_>>(), ["https://example.com"]);
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    /// Registration status
+
+    #[test]
+    fn registration_status_is_default_when_handling_both_schemes() {
+        let status = RegistrationStatus::new(true, true, Some(URL_HANDLER_ID.to_owned()), Some(URL_HANDLER_ID.to_owned()));
+        assert!(status.is_default);
+        assert!(status.to_string().ends_with("FirefoxRouter is the default browser\n"));
+    }
+
+    #[test]
+    fn registration_status_not_default_when_one_scheme_differs() {
+        let status = RegistrationStatus::new(true, true, Some(URL_HANDLER_ID.to_owned()), Some("ChromeHTML".to_owned()));
+        assert!(!status.is_default);
+        let status = RegistrationStatus::new(false, false, None, None);
+        assert!(!status.is_default);
+        assert_eq!(status.to_string(), "Registered application: absent\n\
+            Browser client: absent\n\
+            Default http handler: none\n\
+            Default https handler: none\n\
+            FirefoxRouter is not the default browser\n");
+    }
+
+    #[test]
+    fn registration_status_serializes_to_json() {
+        let status = RegistrationStatus::new(true, false, Some("FirefoxURL".to_owned()), None);
+        let json: serde_json::Value = serde_json::to_value(&status).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "registered_application": true,
+            "start_menu_client": false,
+            "http_handler": "FirefoxURL",
+            "https_handler": null,
+            "is_default": false,
+        }));
+    }
+
+    /// Rewrite rules
+
+    #[test]
+    fn apply_rewrites_with_capture_groups() {
+        let config = config(r#"{"ignored_urls": [], "ignored_urls_regex": [], "rewrite_rules": [
+            {"match": "([?&])utm_source=[^&]*&?", "replace": "$1"}
+        ]}"#);
+        assert_eq!(
+            apply_rewrites("https://example.com/x?utm_source=a&id=5", Some(&config)),
+            "https://example.com/x?id=5",
+        );
+    }
+
+    #[test]
+    fn apply_rewrites_chains_rules_in_order() {
+        let config = config(r#"{"ignored_urls": [], "ignored_urls_regex": [], "rewrite_rules": [
+            {"match": "^http://", "replace": "https://"},
+            {"match": "^https://old\\.example\\.com/(.*)$", "replace": "https://new.example.com/$1"}
+        ]}"#);
+        assert_eq!(
+            apply_rewrites("http://old.example.com/page", Some(&config)),
+            "https://new.example.com/page",
+        );
+    }
+
+    #[test]
+    fn apply_rewrites_without_match_keeps_url() {
+        let config = config(r#"{"ignored_urls": [], "ignored_urls_regex": [], "rewrite_rules": [
+            {"match": "utm_source=[^&]*", "replace": ""}
+        ]}"#);
+        assert_eq!(apply_rewrites("https://example.com/x?id=5", Some(&config)), "https://example.com/x?id=5");
+        assert_eq!(apply_rewrites("https://example.com/x?id=5", None), "https://example.com/x?id=5");
+    }
+
+    /// Query parameter stripping
+
+    #[test]
+    fn clean_query_removes_keys_keeping_order() {
+        assert_eq!(
+            clean_query("https://example.com/x?a=1&utm_source=news&b=2&fbclid=abc&c=3", &tracking_keys()),
+            "https://example.com/x?a=1&b=2&c=3",
+        );
+    }
+
+    #[test]
+    fn clean_query_is_case_insensitive() {
+        assert_eq!(clean_query("https://example.com/x?UTM_Source=a&id=5", &tracking_keys()), "https://example.com/x?id=5");
+    }
+
+    #[test]
+    fn clean_query_drops_empty_query() {
+        assert_eq!(clean_query("https://example.com/x?utm_source=a&gclid=b", &tracking_keys()), "https://example.com/x");
+    }
+
+    #[test]
+    fn clean_query_without_query() {
+        assert_eq!(clean_query("https://example.com/x", &tracking_keys()), "https://example.com/x");
+        assert_eq!(clean_query("https://example.com/x#utm_source=a", &tracking_keys()), "https://example.com/x#utm_source=a");
+    }
+
+    #[test]
+    fn clean_query_removes_duplicate_keys() {
+        assert_eq!(
+            clean_query("https://example.com/x?utm_source=a&id=5&utm_source=b", &tracking_keys()),
+            "https://example.com/x?id=5",
+        );
+    }
+
+    #[test]
+    fn clean_query_keeps_fragment() {
+        assert_eq!(
+            clean_query("https://example.com/x?utm_source=a&id=5#section", &tracking_keys()),
+            "https://example.com/x?id=5#section",
+        );
+        assert_eq!(clean_query("https://example.com/x?fbclid=a#section", &tracking_keys()), "https://example.com/x#section");
+    }
+
+    #[test]
+    fn clean_query_keeps_params_without_value() {
+        assert_eq!(clean_query("https://example.com/x?flag&gclid", &tracking_keys()), "https://example.com/x?flag");
+    }
+
+    /// Firefox path resolution
+
+    #[test]
+    fn resolve_firefox_path_prefers_running_firefox() {
+        let config = config(r#"{"firefox_path": "/portable/firefox"}"#);
+        assert_eq!(resolve_firefox_path(Some(&config), Some(&running_firefox())), PathBuf::from("/running/firefox"));
+        assert_eq!(resolve_firefox_path(None, Some(&running_firefox())), PathBuf::from("/running/firefox"));
+    }
+
+    #[test]
+    fn resolve_firefox_path_uses_configured_path_when_not_running() {
+        let config = config(r#"{"firefox_path": "/portable/firefox"}"#);
+        assert_eq!(resolve_firefox_path(Some(&config), None), PathBuf::from("/portable/firefox"));
+    }
+
+    #[test]
+    fn resolve_firefox_path_forced_overrides_running_firefox() {
+        let config = config(r#"{"firefox_path": "/portable/firefox", "force_firefox_path": true}"#);
+        assert_eq!(resolve_firefox_path(Some(&config), Some(&running_firefox())), PathBuf::from("/portable/firefox"));
+    }
+
+    #[test]
+    fn resolve_firefox_path_forced_without_path_is_ignored() {
+        let config = config(r#"{"force_firefox_path": true}"#);
+        assert_eq!(resolve_firefox_path(Some(&config), Some(&running_firefox())), PathBuf::from("/running/firefox"));
+    }
+
+    /// Fallback browser
+
+    #[test]
+    fn no_fallback_by_default() {
+        let config = config("{}");
+        assert!(fallback_commands(&urls(&["https://example.com"]), &config).is_none());
+    }
+
+    #[test]
+    fn fallback_command_gets_each_url() {
+        let config = config(r#"{"fallback_command": "chrome", "fallback_args": ["--new-tab", "{url}"]}"#);
+        let commands = fallback_commands(&urls(&["https://a.com", "https://b.com"]), &config).unwrap();
+        let command_lines: Vec<_> = commands.iter().map(command_line).collect();
+        assert_eq!(command_lines, [["chrome", "--new-tab", "https://a.com"], ["chrome", "--new-tab", "https://b.com"]]);
+    }
+
+    #[test]
+    fn fallback_to_default_browser() {
+        let config = config(r#"{"fallback_to_default_browser": true}"#);
+        let commands = fallback_commands(&urls(&["https://a.com"]), &config).unwrap();
+        let expected: Vec<_> = DEFAULT_BROWSER_OPENER.iter().copied().chain(["https://a.com"]).collect();
+        assert_eq!(command_line(&commands[0]), expected);
+    }
+
+    #[test]
+    fn fallback_marks_launched_process() {
+        let config = config(r#"{"fallback_command": "chrome"}"#);
+        let commands = fallback_commands(&urls(&["https://a.com"]), &config).unwrap();
+        assert!(commands[0].get_envs().any(|(key, value)| key == FALLBACK_ENV_VAR && value.is_some()));
+    }
+
+    /// Config lint
+
+    #[test]
+    fn lint_reports_duplicate_globs() {
+        let config = config(r#"{"ignored_urls": ["https://ads.com/**", "https://b.com", "https://ads.com/**"], "allowed_urls": ["https://a.com", "https://b.com"]}"#);
+        assert_eq!(lint_config(&config), vec![ConfigWarning::DuplicateGlob { list: "ignored_urls", first: 0, duplicate: 2 }]);
+        assert_eq!(lint_config(&config)[0].to_string(), "ignored_urls[2] is the same as ignored_urls[0]");
+    }
+
+    #[test]
+    fn lint_reports_shadowed_rules() {
+        let config = config(r#"{
+            "browser_rules": [{"pattern": "https://meet.com/**", "command": "chromium"}],
+            "profile_rules": [
+                {"pattern": "https://work.com/**", "profile": "Work"},
+                {"pattern": "https://work.com/docs/**", "profile": "Docs"},
+                {"pattern": "https://meet.com/room", "profile": "Meet"}
+            ]
+        }"#);
+        assert_eq!(lint_config(&config), vec![
+            ConfigWarning::ShadowedRule { rule: MatchedRule::Profile(1), shadowed_by: MatchedRule::Profile(0) },
+            ConfigWarning::ShadowedRule { rule: MatchedRule::Profile(2), shadowed_by: MatchedRule::Browser(0) },
+        ]);
+    }
+
+    #[test]
+    fn lint_accepts_rules_winning_by_priority_or_order() {
+        let config = config(r#"{
+            "profile_rules": [
+                {"pattern": "https://work.com/docs/**", "profile": "Docs"},
+                {"pattern": "https://work.com/**", "profile": "Work"},
+                {"pattern": "https://work.com/admin/**", "profile": "Admin", "priority": 1},
+                {"pattern": "https://other.com/**", "profile": "Other"}
+            ]
+        }"#);
+        assert_eq!(lint_config(&config), vec![]);
+    }
+
+    #[test]
+    fn lint_reports_ignored_rules() {
+        let config = config(r#"{
+            "ignored_urls": ["https://corp.com/**"],
+            "profile_rules": [
+                {"pattern": "https://corp.com/wiki/**", "profile": "Work"},
+                {"pattern": "https://corp.com/hr/**", "profile": "Work", "priority": 1}
+            ]
+        }"#);
+        assert_eq!(lint_config(&config), vec![ConfigWarning::IgnoredRule { rule: MatchedRule::Profile(0), ignored_by: 0 }]);
+        assert!(lint_config(&config)[0].to_string().contains("ignored by ignored_urls[0]"));
+    }
+
+    #[test]
+    fn lint_accepts_rules_unignored_by_negated_glob() {
+        let config = config(r#"{
+            "ignored_urls": ["https://corp.com/**", "!https://corp.com/wiki/**"],
+            "profile_rules": [{"pattern": "https://corp.com/wiki/**", "profile": "Work"}]
+        }"#);
+        assert_eq!(lint_config(&config), vec![]);
+    }
+
+    /// Require running
+
+    #[test]
+    fn require_running_hands_urls_to_default_browser() {
+        let config = launch_config(r#""require_running": true"#);
+        let commands = launched_commands(&["https://example.com/", "about:config"], &config);
+        let opener = |url: &str| DEFAULT_BROWSER_OPENER.iter().copied().chain([url]).map(str::to_owned).collect::<Vec<_>>();
+        assert_eq!(commands, vec![opener("https://example.com/"), opener("about:config")]);
+    }
+
+    #[test]
+    fn require_running_opens_firefox_when_itself_the_default_browser() {
+        let config = launch_config(r#""require_running": true"#);
+        let launcher = RecordingLauncher { is_default_browser: true, ..Default::default() };
+        open_links(urls(&["https://example.com/"]), Some(&config), &launcher, &FixedProcesses::default()).unwrap();
+        assert_eq!(launcher.commands.into_inner(), vec![urls(&["/opt/firefox", "-P", "Personal", "-url", "https://example.com/"])]);
+    }
+
+    #[test]
+    fn require_running_launches_running_firefox() {
+        let config = launch_config(r#""require_running": true"#);
+        let processes = FixedProcesses { processes: vec![named_firefox("Work")], frontmost: None };
+        let commands = launched_commands_with(&["https://example.com/"], &config, &processes);
+        // The active profile detection stays disabled, so the running Firefox only lets the launch through
+        assert_eq!(commands, vec![urls(&["/opt/firefox", "-P", "Personal", "-url", "https://example.com/"])]);
+    }
+
+    #[test]
+    fn require_running_never_hands_urls_to_itself() {
+        assert!(default_browser_commands(&urls(&["https://a.com"]), true).is_none());
+        let commands = default_browser_commands(&urls(&["https://a.com"]), false).unwrap();
+        assert!(commands[0].get_envs().any(|(key, value)| key == FALLBACK_ENV_VAR && value.is_some()));
+    }
+
+    /// Match explanation
+
+    #[test]
+    fn matches_any_returns_deciding_glob() {
+        let config = config(r#"{"ignored_urls": ["https://a.com/**", "https://docs.com/**", "!https://docs.com/public/**"], "ignored_urls_regex": []}"#);
+        assert_eq!(matches_any(&config.ignored_urls, "https://docs.com/private"), Some(1));
+        assert_eq!(matches_any(&config.ignored_urls, "https://docs.com/public/a"), None);
+        assert_eq!(matches_any(&config.ignored_urls, "https://b.com"), None);
+    }
+
+    #[test]
+    fn explain_match_reports_ignoring_rules() {
+        let config = config(r#"{"ignored_urls": ["https://docs.com/**"], "ignored_urls_regex": ["^https://ads\\."]}"#);
+        let explanation = explain_match("https://docs.com/a", &config);
+        assert_eq!(explanation.ignored_by_glob, Some(0));
+        assert!(explanation.is_filtered_out());
+        assert_eq!(explanation.final_url, None);
+
+        let explanation = explain_match("https://ads.example.com", &config);
+        assert_eq!(explanation.ignored_by_regex.as_deref(), Some("^https://ads\\."));
+        assert!(explanation.to_string().contains("Result: filtered out"));
+    }
+
+    #[test]
+    fn explain_match_reports_allowlist() {
+        let config = config(r#"{"ignored_urls": [], "ignored_urls_regex": [], "allowed_urls": ["https://*.work.com/**"]}"#);
+        let explanation = explain_match("https://example.com", &config);
+        assert!(!explanation.is_allowed);
+        assert!(explanation.to_string().contains("Not allowed by any allowed_urls"));
+        assert_eq!(explain_match("https://git.work.com/a", &config).allowed_by_glob, Some(0));
+    }
+
+    #[test]
+    fn explain_match_reports_routing() {
+        let config = config(r#"{
+            "ignored_urls": [],
+            "ignored_urls_regex": [],
+            "strip_query_params": ["utm_source"],
+            "browser_rules": [{"pattern": "https://teams.com/**", "command": "msedge.exe"}],
+            "profile_rules": [
+                {"pattern": "https://other.com/**", "profile": "Other"},
+                {"pattern": "https://*.work.com/**", "profile": "Work", "action": "private_window"}
+            ]
+        }"#);
+        let explanation = explain_match("https://git.work.com/a?utm_source=x", &config);
+        assert_eq!(explanation.final_url.as_deref(), Some("https://git.work.com/a"));
+        assert_eq!(explanation.profile_rule, Some(1));
+        assert_eq!(explanation.route, route(Some("Work"), RuleAction::PrivateWindow));
+        assert!(explanation.to_string().contains("opened in profile 'Work' in a private window due to profile_rules[1]"));
+
+        let explanation = explain_match("https://teams.com/chat", &config);
+        assert_eq!(explanation.browser_rule, Some(0));
+        assert_eq!(explanation.profile_rule, None);
+
+        let explanation = explain_match("https://example.com", &config);
+        assert_eq!(explanation.route, Route::default());
+        assert!(explanation.to_string().contains("opened in the active profile, no profile rule matched"));
+    }
+
+    #[test]
+    fn explain_match_reports_glob_regexes() {
+        let config = config(r#"{
+            "ignored_urls": ["https://ads.com/**"],
+            "ignored_urls_regex": [],
+            "profile_rules": [{"pattern": "https://*.work.com/**", "profile": "Work"}]
+        }"#);
+        let explanation = explain_match("https://git.work.com/a", &config);
+        let (with_protocol, _) = config.profile_rules[0].pattern.regex_source();
+        let expected = ("profile_rules[0]".to_owned(), "https://*.work.com/**".to_owned(), with_protocol.to_owned());
+        assert_eq!(explanation.glob_regexes, vec![expected]);
+        assert!(explanation.to_string().contains("Pattern of profile_rules[0]: https://*.work.com/**"));
+        assert!(explanation.to_string().contains(&format!("Regex of profile_rules[0]: {with_protocol}")));
+
+        let explanation = explain_match("https://ads.com/banner", &config);
+        assert_eq!(explanation.glob_regexes[0].0, "ignored_urls[0]");
+        assert!(explain_match("https://example.com", &config).glob_regexes.is_empty());
+    }
+}
@@ -1,10 +1,13 @@
+use std::borrow::Cow;
 use std::path::PathBuf;
 
+/// The absolute path of the running exe. The first arg isn't relied upon, since it's relative when
+/// launched through `PATH` or from another folder, which would make it relative to the working dir.
 pub fn get_current_exe_path() -> PathBuf {
-    std::env::args().next().map(PathBuf::from)
-        .unwrap_or_else(|| {
-            debug_log!("Couldn't get current exe path from args, trying to get it from env");
-            std::env::current_exe().expect("Should be able to get current exe path")
+    std::env::current_exe()
+        .unwrap_or_else(|e| {
+            debug_log!("Couldn't get current exe path from env ({e}), trying to get it from args");
+            std::env::args().next().map(PathBuf::from).expect("Should be able to get current exe path")
         })
 }
 
@@ -12,4 +15,70 @@ pub fn load_env_file() {
     #[cfg(debug_assertions)] {
         dotenvy::from_path_override(".env").ok();
     }
-}
\ No newline at end of file
+}
+
+/// Percent-encodes every byte of `value` except the unreserved characters of RFC 3986.
+pub fn percent_encode(value: &str) -> String {
+    percent_encode_except(value, b"")
+}
+
+/// Like [`percent_encode`], but keeps the `/` separators and the `:` of drive letters, so `value`
+/// can be used as the path of a `file://` URL.
+pub fn percent_encode_path(value: &str) -> String {
+    percent_encode_except(value, b"/:")
+}
+
+fn percent_encode_except(value: &str, kept: &[u8]) -> String {
+    let mut encoded = String::with_capacity(value.len() * 3);
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') || kept.contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+/// Decodes the percent-encoded bytes that don't change the meaning of a URL (unreserved characters,
+/// spaces and non-ASCII characters), so equivalent URLs can be compared. Encoded reserved characters
+/// like `%2F` are structurally different from their decoded form, so they are kept, only with their
+/// hex digits uppercased. URLs that wouldn't decode to valid UTF-8 are returned unchanged.
+pub fn normalize_percent_encoding(url: &str) -> Cow<'_, str> {
+    if !url.contains('%') {
+        return Cow::Borrowed(url);
+    }
+    let bytes = url.as_bytes();
+    let mut normalized = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let decoded = bytes.get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        let Some(byte) = decoded else {
+            normalized.push(bytes[index]);
+            index += 1;
+            continue;
+        };
+        if !byte.is_ascii() || byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~' | b' ') {
+            normalized.push(byte);
+        } else {
+            normalized.extend_from_slice(format!("%{byte:02X}").as_bytes());
+        }
+        index += 3;
+    }
+    match String::from_utf8(normalized) {
+        Ok(normalized) => Cow::Owned(normalized),
+        Err(_) => Cow::Borrowed(url),
+    }
+}
+
+/// An empty folder in the temp folder for the tests called `name`, unique to the test process.
+#[cfg(test)]
+pub fn test_folder(name: &str) -> PathBuf {
+    let folder = std::env::temp_dir().join(format!("FirefoxRouter-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    folder
+}
@@ -0,0 +1,203 @@
+/// Returns the PID of the frontmost visible top-level window for which `is_wanted` returns true.
+/// Windows are enumerated in z-order, so that's the window of the process that was used last (the
+/// foreground one, when it qualifies), even if another app has the focus right now.
+#[cfg(windows)]
+pub fn frontmost_window_pid(mut is_wanted: impl FnMut(u32) -> bool) -> Option<u32> {
+    use windows_sys::Win32::Foundation::{BOOL, HWND, LPARAM};
+    use windows_sys::Win32::UI::WindowsAndMessaging::{EnumWindows, GetWindowThreadProcessId, IsWindowVisible};
+
+    struct Search<'a> {
+        is_wanted: &'a mut dyn FnMut(u32) -> bool,
+        pid: Option<u32>,
+    }
+
+    unsafe extern "system" fn visit_window(window: HWND, lparam: LPARAM) -> BOOL {
+        // SAFETY: lparam is the pointer to the Search passed to EnumWindows below, which outlives the
+        // enumeration, and GetWindowThreadProcessId only writes the PID to a valid u32.
+        let (search, pid) = unsafe {
+            let search = &mut *(lparam as *mut Search);
+            let mut pid = 0u32;
+            if IsWindowVisible(window) == 0 || GetWindowThreadProcessId(window, &mut pid) == 0 {
+                return 1;
+            }
+            (search, pid)
+        };
+        if !(search.is_wanted)(pid) {
+            return 1;
+        }
+        search.pid = Some(pid);
+        0 // Stop the enumeration
+    }
+
+    let mut search = Search { is_wanted: &mut is_wanted, pid: None };
+    // SAFETY: the callback only dereferences lparam as the Search it points to, which lives until
+    // EnumWindows returns.
+    unsafe {
+        EnumWindows(Some(visit_window), &mut search as *mut Search as LPARAM);
+    }
+    if search.pid.is_none() {
+        debug_log!("Couldn't find a visible window matching the criteria");
+    }
+    search.pid
+}
+
+#[cfg(not(windows))]
+pub fn frontmost_window_pid(_is_wanted: impl FnMut(u32) -> bool) -> Option<u32> {
+    None
+}
+
+/// Shows an error to the user. Release builds have no console, so a message box is used instead.
+#[cfg(all(windows, not(debug_assertions)))]
+pub fn show_error(msg: &str) {
+    use windows_sys::Win32::UI::WindowsAndMessaging::{MessageBoxW, MB_ICONERROR, MB_OK};
+
+    let text = to_wide(msg);
+    let caption = to_wide("FirefoxRouter");
+    // SAFETY: both strings are null-terminated UTF-16 buffers that outlive the call.
+    unsafe {
+        MessageBoxW(std::ptr::null_mut(), text.as_ptr(), caption.as_ptr(), MB_OK | MB_ICONERROR);
+    }
+}
+
+#[cfg(any(not(windows), debug_assertions))]
+pub fn show_error(msg: &str) {
+    eprintln!("{msg}");
+}
+
+/// Asks the user to pick one of `options` through message boxes, one option at a time: "Yes" picks
+/// it, "No" moves on to the next one (going back to the first after the last) and "Cancel" gives up.
+#[cfg(windows)]
+pub fn choose_option(title: &str, prompt: &str, options: &[String]) -> Option<usize> {
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        MessageBoxW, IDNO, IDYES, MB_ICONQUESTION, MB_SETFOREGROUND, MB_TOPMOST, MB_YESNOCANCEL,
+    };
+
+    let caption = to_wide(title);
+    for (index, option) in options.iter().enumerate().cycle() {
+        let text = to_wide(&format!(
+            "{prompt}\n\n{option} ({}/{})\n\nYes: pick it\nNo: show the next one\nCancel: don't pick any",
+            index + 1,
+            options.len(),
+        ));
+        // SAFETY: both strings are null-terminated UTF-16 buffers that outlive the call.
+        let result = unsafe {
+            MessageBoxW(std::ptr::null_mut(), text.as_ptr(), caption.as_ptr(), MB_YESNOCANCEL | MB_ICONQUESTION | MB_SETFOREGROUND | MB_TOPMOST)
+        };
+        match result {
+            IDYES => return Some(index),
+            IDNO => continue,
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// The options are listed with `zenity`, if installed.
+#[cfg(target_os = "linux")]
+pub fn choose_option(title: &str, prompt: &str, options: &[String]) -> Option<usize> {
+    let output = std::process::Command::new("zenity")
+        .args(["--list", "--title", title, "--text", prompt, "--column", "Option", "--hide-header"])
+        .args(options)
+        .stderr(std::process::Stdio::null())
+        .output();
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            debug_log!("Couldn't show the chooser: {e}");
+            return None;
+        }
+    };
+    let chosen = String::from_utf8_lossy(&output.stdout);
+    options.iter().position(|it| it == chosen.trim_end_matches('\n'))
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn choose_option(_title: &str, _prompt: &str, _options: &[String]) -> Option<usize> {
+    None
+}
+
+/// Shows a balloon notification from a tray icon, which is removed once the balloon had time to be
+/// seen. Notifications are merely informative, so failing to show one is silently ignored.
+#[cfg(windows)]
+pub fn show_notification(title: &str, message: &str) {
+    use windows_sys::Win32::UI::Shell::{Shell_NotifyIconW, NIF_ICON, NIF_INFO, NIF_TIP, NIIF_INFO, NIM_ADD, NIM_DELETE, NOTIFYICONDATAW};
+    use windows_sys::Win32::UI::WindowsAndMessaging::{CreateWindowExW, DestroyWindow, LoadIconW, HWND_MESSAGE, IDI_APPLICATION};
+
+    let class = to_wide("STATIC");
+    // SAFETY: the class name is a null-terminated UTF-16 buffer that outlives the call, and a
+    // message-only window of a predefined class needs no other parameters.
+    let window = unsafe {
+        CreateWindowExW(0, class.as_ptr(), std::ptr::null(), 0, 0, 0, 0, 0, HWND_MESSAGE, std::ptr::null_mut(), std::ptr::null_mut(), std::ptr::null())
+    };
+    if window.is_null() {
+        return;
+    }
+    // SAFETY: NOTIFYICONDATAW is a plain C struct, for which all zeroes is a valid value.
+    let mut data: NOTIFYICONDATAW = unsafe { std::mem::zeroed() };
+    data.cbSize = size_of::<NOTIFYICONDATAW>() as u32;
+    data.hWnd = window;
+    data.uID = 1;
+    data.uFlags = NIF_ICON | NIF_TIP | NIF_INFO;
+    // SAFETY: IDI_APPLICATION is a predefined icon, which is loaded without a module.
+    data.hIcon = unsafe { LoadIconW(std::ptr::null_mut(), IDI_APPLICATION) };
+    data.dwInfoFlags = NIIF_INFO;
+    copy_wide(&mut data.szTip, title);
+    copy_wide(&mut data.szInfoTitle, title);
+    copy_wide(&mut data.szInfo, message);
+    // SAFETY: `data` is fully initialized and `window` stays alive until the icon is removed.
+    unsafe {
+        if Shell_NotifyIconW(NIM_ADD, &data) != 0 {
+            std::thread::sleep(NOTIFICATION_DURATION);
+            Shell_NotifyIconW(NIM_DELETE, &data);
+        }
+        DestroyWindow(window);
+    }
+}
+
+/// How long the tray icon is kept around for its balloon to be shown.
+#[cfg(windows)]
+const NOTIFICATION_DURATION: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Copies `value` into a fixed-size UTF-16 buffer, truncating it to leave room for the null terminator.
+#[cfg(windows)]
+fn copy_wide<const N: usize>(buffer: &mut [u16; N], value: &str) {
+    for (slot, c) in buffer.iter_mut().take(N - 1).zip(value.encode_utf16()) {
+        *slot = c;
+    }
+}
+
+/// Notifications are shown through `notify-send`, if installed.
+#[cfg(target_os = "linux")]
+pub fn show_notification(title: &str, message: &str) {
+    let result = std::process::Command::new("notify-send")
+        .args(["--app-name=FirefoxRouter", "--expire-time=5000", title, message])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+    if let Err(e) = result {
+        debug_log!("Couldn't show notification: {e}");
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn show_notification(_title: &str, _message: &str) {}
+
+#[cfg(windows)]
+fn to_wide(value: &str) -> Vec<u16> {
+    value.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+/// Release builds use the windows subsystem, which doesn't get a console, so attach to the console
+/// of the parent process (if any) to make informational output visible when run from a terminal.
+#[cfg(all(windows, not(debug_assertions)))]
+pub fn attach_parent_console() {
+    use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+
+    // SAFETY: AttachConsole has no preconditions, it simply fails if there's no console to attach to.
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(any(not(windows), debug_assertions))]
+pub fn attach_parent_console() {}
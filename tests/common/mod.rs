@@ -0,0 +1,62 @@
+//! Test doubles of the routing shared by the integration tests, so they only rely on what the
+//! library exports.
+
+use firefox_router::{open_links, AppConfig, FirefoxInfo, Launcher, ProcessSource};
+use std::cell::RefCell;
+use std::process::Command;
+
+/// Records the commands instead of running them. It's a dry run, so nothing waits for the Firefox
+/// it launches to start.
+#[derive(Default)]
+pub struct RecordingLauncher {
+    pub commands: RefCell<Vec<Vec<String>>>,
+    /// Whether FirefoxRouter is taken for the system's default browser.
+    pub is_default_browser: bool,
+}
+
+impl Launcher for RecordingLauncher {
+    fn launch(&self, command: &mut Command) -> std::io::Result<()> {
+        let args = std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|it| it.to_string_lossy().into_owned())
+            .collect();
+        self.commands.borrow_mut().push(args);
+        Ok(())
+    }
+
+    fn is_dry_run(&self) -> bool {
+        true
+    }
+
+    fn is_default_browser(&self) -> bool {
+        self.is_default_browser
+    }
+}
+
+/// A fixed set of running Firefox processes, `frontmost` owning the frontmost window.
+#[derive(Default)]
+pub struct FixedProcesses {
+    pub processes: Vec<FirefoxInfo>,
+    pub frontmost: Option<FirefoxInfo>,
+}
+
+impl ProcessSource for FixedProcesses {
+    fn firefox_processes(&self) -> Vec<FirefoxInfo> {
+        self.processes.clone()
+    }
+
+    fn frontmost_firefox(&self) -> Option<FirefoxInfo> {
+        self.frontmost.clone()
+    }
+}
+
+pub fn urls(urls: &[&str]) -> Vec<String> {
+    urls.iter().map(|it| it.to_string()).collect()
+}
+
+/// The commands opening `args` launches with `config` while `processes` are running.
+pub fn launched_commands_with(args: &[&str], config: &AppConfig, processes: &FixedProcesses) -> Vec<Vec<String>> {
+    let launcher = RecordingLauncher::default();
+    open_links(urls(args), Some(config), &launcher, processes).unwrap();
+    launcher.commands.into_inner()
+}
@@ -0,0 +1,137 @@
+//! End to end tests of the routing: a config and a set of running Firefox processes go in, the
+//! commands that would be launched come out. The single steps are unit tested in `router.rs`, these
+//! check how they combine.
+
+mod common;
+
+use common::{launched_commands_with, urls, FixedProcesses};
+use firefox_router::config::RuleAction;
+use firefox_router::{open_links, resolve, AppConfig, Decision, FirefoxInfo, Launcher, ProcessSource, Route};
+
+/// A config launching `/opt/firefox`, in the `Personal` profile when no Firefox is running.
+fn config(rules: &str) -> AppConfig {
+    let rules = if rules.is_empty() { String::new() } else { format!(", {rules}") };
+    let json = format!(r#"{{ "firefox_path": "/opt/firefox", "default_profile": "Personal"{rules} }}"#);
+    serde_json::from_str(&json).unwrap_or_else(|e| panic!("Failed to parse config '{json}': {e}"))
+}
+
+fn running(profile: &str, start_time: u64) -> FirefoxInfo {
+    FirefoxInfo::new("/running/firefox", Some(profile), start_time)
+}
+
+#[test]
+fn no_firefox_running_opens_default_profile() {
+    let commands = launched_commands_with(&["https://example.com/"], &config(""), &FixedProcesses::default());
+    assert_eq!(commands, [urls(&["/opt/firefox", "-P", "Personal", "-url", "https://example.com/"])]);
+}
+
+#[test]
+fn single_running_profile_is_used() {
+    let processes = FixedProcesses { processes: vec![running("Work", 100)], frontmost: None };
+    let commands = launched_commands_with(&["https://example.com/", "https://rust-lang.org/"], &config(""), &processes);
+    assert_eq!(commands, [urls(&[
+        "/running/firefox", "-P", "Work", "-url", "https://example.com/", "-url", "https://rust-lang.org/",
+    ])]);
+}
+
+#[test]
+fn frontmost_of_several_running_profiles_is_used() {
+    let processes = FixedProcesses {
+        processes: vec![running("Work", 100), running("Gaming", 200)],
+        frontmost: Some(running("Work", 100)),
+    };
+    let commands = launched_commands_with(&["https://example.com/"], &config(""), &processes);
+    assert_eq!(commands, [urls(&["/running/firefox", "-P", "Work", "-url", "https://example.com/"])]);
+}
+
+#[test]
+fn last_started_of_several_running_profiles_is_used_without_frontmost() {
+    let processes = FixedProcesses {
+        processes: vec![running("Work", 100), running("Gaming", 200), FirefoxInfo::new("/running/firefox", None, 300)],
+        frontmost: None,
+    };
+    let commands = launched_commands_with(&["https://example.com/"], &config(""), &processes);
+    assert_eq!(commands, [urls(&["/running/firefox", "-P", "Gaming", "-url", "https://example.com/"])]);
+}
+
+#[test]
+fn all_urls_filtered_launches_nothing() {
+    let config = config(r#""ignored_urls": ["https://ads.com/**", "https://*.tracker.net/**"]"#);
+    let processes = FixedProcesses { processes: vec![running("Work", 100)], frontmost: None };
+    let commands = launched_commands_with(&["https://ads.com/pixel", "https://a.tracker.net/t?id=1"], &config, &processes);
+    assert_eq!(commands, Vec::<Vec<String>>::new());
+}
+
+#[test]
+fn matched_profile_rule_opens_beside_running_profile() {
+    let config = config(r#""profile_rules": [{ "pattern": "https://*.work.com/**", "profile": "Work" }]"#);
+    let processes = FixedProcesses { processes: vec![running("Personal", 100)], frontmost: None };
+    let commands = launched_commands_with(&["https://app.work.com/a", "https://example.com/", "https://docs.work.com/b"], &config, &processes);
+    assert_eq!(commands, [
+        urls(&["/running/firefox", "-P", "Work", "-url", "https://app.work.com/a", "-url", "https://docs.work.com/b"]),
+        urls(&["/running/firefox", "-P", "Personal", "-url", "https://example.com/"]),
+    ]);
+}
+
+#[test]
+fn matched_profile_rule_naming_running_profile_shares_its_launch() {
+    let config = config(r#""profile_rules": [{ "pattern": "https://*.work.com/**", "profile": "Work" }]"#);
+    let processes = FixedProcesses { processes: vec![running("Work", 100)], frontmost: None };
+    let commands = launched_commands_with(&["https://app.work.com/a", "https://example.com/"], &config, &processes);
+    assert_eq!(commands, [urls(&[
+        "/running/firefox", "-P", "Work", "-url", "https://app.work.com/a", "-url", "https://example.com/",
+    ])]);
+}
+
+#[test]
+fn every_step_applies_to_a_mixed_batch() {
+    let config = config(r#"
+        "ignored_urls": ["https://ads.com/**"],
+        "strip_query_params": ["utm_source"],
+        "browser_rules": [{ "pattern": "https://meet.google.com/**", "command": "/usr/bin/chromium", "args": ["--new-window", "{url}"] }],
+        "profile_rules": [{ "pattern": "https://*.work.com/**", "profile": "Work" }]
+    "#);
+    let processes = FixedProcesses {
+        processes: vec![running("Personal", 100), running("Gaming", 200)],
+        frontmost: Some(running("Personal", 100)),
+    };
+    let commands = launched_commands_with(&[
+        "https://ads.com/pixel",
+        "https://meet.google.com/abc",
+        "https://app.work.com/a?utm_source=mail",
+        "example.com",
+    ], &config, &processes);
+    assert_eq!(commands, [
+        urls(&["/usr/bin/chromium", "--new-window", "https://meet.google.com/abc"]),
+        urls(&["/running/firefox", "-P", "Work", "-url", "https://app.work.com/a"]),
+        urls(&["/running/firefox", "-P", "Personal", "-url", "https://example.com"]),
+    ]);
+}
+
+#[test]
+fn custom_doubles_can_drive_open_links() {
+    // The traits are public so other tools can plug in their own launcher and process list
+    struct NoProcesses;
+    impl ProcessSource for NoProcesses {
+        fn firefox_processes(&self) -> Vec<FirefoxInfo> {
+            Vec::new()
+        }
+    }
+    struct FailingLauncher;
+    impl Launcher for FailingLauncher {
+        fn launch(&self, _: &mut std::process::Command) -> std::io::Result<()> {
+            Err(std::io::ErrorKind::NotFound.into())
+        }
+        fn is_dry_run(&self) -> bool {
+            true
+        }
+    }
+    assert!(open_links(urls(&["https://example.com/"]), Some(&config("")), &FailingLauncher, &NoProcesses).is_err());
+}
+
+#[test]
+fn resolve_tells_the_route_of_a_url() {
+    let config = config(r#""profile_rules": [{ "pattern": "https://*.work.com/**", "profile": "Work" }]"#);
+    let route = Route { profile_name: Some("Work".to_owned()), profile_pool: None, action: RuleAction::Open };
+    assert_eq!(resolve("https://app.work.com/a", &config), Decision::OpenInFirefox { url: "https://app.work.com/a".to_owned(), route });
+}